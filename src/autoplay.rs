@@ -1,21 +1,101 @@
-use std::time::Duration;
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    sync::{atomic::AtomicBool, Arc},
+    time::{Duration, Instant},
+};
 
-use arrayvec::ArrayVec;
+use crate::{
+    chess_game::{ChessGame, MAX_GAME_LENGTH},
+    game_result::{GameResult, Termination},
+    move_struct::Move,
+    options::EngineOptions,
+    pgn::{self, PgnGame},
+    search::{get_best_move_in_time, SearchLimits},
+    transposition_table::TranspositionTable,
+};
 
-use crate::{chess_game::ChessGame, search::get_best_move_in_time};
+/// The moves of the line in SAN, starting from the current position of the game
+fn san_line(game: &ChessGame, line: &[Move]) -> String {
+    let mut game = game.clone();
+    let mut moves = vec![];
+    for &_move in line {
+        moves.push(_move.san_notation(&mut game));
+        game.push(_move);
+    }
+    moves.join(" ")
+}
 
-pub fn autoplay(millis: u64) {
+/// Plays a game against itself, printing it in PGN at the end,
+/// and appending it to the file at pgn_path if one is given
+pub fn autoplay(millis: u64, pgn_path: Option<String>) {
     let mut game = ChessGame::default();
+    let options = EngineOptions::default();
+    let tt = TranspositionTable::new(options.hash_size_mb);
+    let mut comments = vec![];
+    let limits = SearchLimits {
+        move_time: Some(Duration::from_millis(millis)),
+        ..Default::default()
+    };
 
-    loop {
-        let mut moves = ArrayVec::new();
-        game.get_moves(&mut moves, true);
+    let result = loop {
         println!("{}", game.get_pgn());
         println!("{}", &game);
-        let next_move = match get_best_move_in_time(&game, Duration::from_millis(millis)) {
-            Some(_move) => _move,
-            None => break,
+
+        if let Some(result) = game.result() {
+            break result;
+        }
+        // Adjudicated as a draw, since the game can't be made any longer
+        if game.len() >= MAX_GAME_LENGTH {
+            break GameResult::Draw(Termination::Adjudication);
+        }
+
+        let start = Instant::now();
+        let should_stop = Arc::new(AtomicBool::new(false));
+        let Some(search) = get_best_move_in_time(&game, &limits, &options, &tt, should_stop) else {
+            break GameResult::Draw(Termination::Adjudication);
         };
-        game.push_history(next_move);
+        println!("Principal variation: {}", san_line(&game, &search.pv));
+        comments.push(Some(pgn::engine_comment(
+            search.score,
+            search.depth,
+            start.elapsed(),
+        )));
+        game.push_history(search.best_move);
+    };
+
+    let mut pgn_game = PgnGame::new(game, result.pgn_result());
+    pgn_game.comments = comments;
+    pgn_game.set_tag("Event", "Autoplay");
+    pgn_game.set_tag("Date", &pgn::today());
+    pgn_game.set_tag("White", "daniel_chess");
+    pgn_game.set_tag("Black", "daniel_chess");
+    pgn_game.set_tag(
+        "Termination",
+        match result.termination() {
+            Termination::Adjudication => "adjudication",
+            _ => "normal",
+        },
+    );
+
+    let pgn = pgn_game.to_pgn();
+    println!("{}", pgn);
+
+    let outcome = match result {
+        GameResult::WhiteWins(_) => "White wins",
+        GameResult::BlackWins(_) => "Black wins",
+        GameResult::Draw(_) => "Draw",
+    };
+    println!("{} by {}", outcome, result.termination());
+
+    if let Some(path) = pgn_path {
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| writeln!(file, "{}", pgn));
+        if let Err(err) = written {
+            eprintln!("Could not save the game to {}: {}", path, err);
+        }
     }
 }
@@ -1,4 +1,9 @@
-use crate::{chess_game::ChessGame, move_struct::Move, search::get_best_move_entry};
+use crate::{
+    chess_game::ChessGame,
+    move_struct::Move,
+    search::{get_best_move_entry, SearchContext},
+    transposition_table::{TranspositionTable, DEFAULT_SIZE_MB},
+};
 
 use std::{sync::atomic::AtomicBool, time::Instant};
 
@@ -19,13 +24,18 @@ pub fn run_benchmark(depth: u8, steps: u8) {
 
     let mut game = ChessGame::default();
     let mut durations = vec![];
+    let mut nodes = 0;
 
     let atomic_false = AtomicBool::new(false);
+    let tt = TranspositionTable::new(DEFAULT_SIZE_MB);
 
     'outer: loop {
         let now = Instant::now();
-        get_best_move_entry(game.clone(), &atomic_false, depth).unwrap();
+        tt.new_search();
+        let mut context = SearchContext::new(&atomic_false, &tt);
+        get_best_move_entry(game.clone(), &mut context, depth).unwrap();
         durations.push(now.elapsed());
+        nodes += context.nodes;
 
         for _ in 0..steps {
             let Some(_move) = moves.next() else {
@@ -41,9 +51,11 @@ pub fn run_benchmark(depth: u8, steps: u8) {
 
     println!(
         "Depth: {}, Steps: {}
-Geometric Mean: {:.2} ms",
+Geometric Mean: {:.2} ms
+Nodes: {}",
         depth,
         steps,
-        geo_mean * 1000.0
+        geo_mean * 1000.0,
+        nodes
     );
 }
@@ -0,0 +1,469 @@
+// Source: https://www.chessprogramming.org/Bitboards
+//
+// Bit i of a bitboard represents the square with Position::as_usize() == i,
+// so a1 is the least significant bit, h1 is bit 7 and h8 is the most significant bit
+
+use crate::chess_game::Players;
+use crate::position::Position;
+
+pub type Bitboard = u64;
+
+pub const RANK_1: Bitboard = 0xFF;
+pub const RANK_8: Bitboard = RANK_1 << 56;
+/// a1 is a dark square, b1 a light one
+pub const LIGHT_SQUARES: Bitboard = 0x55AA_55AA_55AA_55AA;
+pub const DARK_SQUARES: Bitboard = !LIGHT_SQUARES;
+
+#[inline]
+pub fn square(position: Position) -> Bitboard {
+    1 << position.as_usize()
+}
+
+/// Iterator over the positions of the set bits of a bitboard
+pub struct Squares(Bitboard);
+
+impl Iterator for Squares {
+    type Item = Position;
+
+    #[inline]
+    fn next(&mut self) -> Option<Position> {
+        if self.0 == 0 {
+            return None;
+        }
+        let index = self.0.trailing_zeros() as usize;
+        // Clear the least significant bit
+        self.0 &= self.0 - 1;
+        Some(Position::from_usize(index))
+    }
+}
+
+#[inline]
+pub fn squares(bitboard: Bitboard) -> Squares {
+    Squares(bitboard)
+}
+
+/// Bitboard of the squares reached by moving from index by the given deltas once
+const fn step_attacks(index: usize, deltas: &[(i8, i8)]) -> Bitboard {
+    let (row, col) = ((index / 8) as i8, (index % 8) as i8);
+    let mut attacks = 0;
+    let mut i = 0;
+    while i < deltas.len() {
+        let (new_row, new_col) = (row + deltas[i].0, col + deltas[i].1);
+        if new_row >= 0 && new_row < 8 && new_col >= 0 && new_col < 8 {
+            attacks |= 1 << (new_row * 8 + new_col);
+        }
+        i += 1;
+    }
+    attacks
+}
+
+const fn step_attacks_table(deltas: &[(i8, i8)]) -> [Bitboard; 64] {
+    let mut table = [0; 64];
+    let mut index = 0;
+    while index < 64 {
+        table[index] = step_attacks(index, deltas);
+        index += 1;
+    }
+    table
+}
+
+static KNIGHT_ATTACKS: [Bitboard; 64] = step_attacks_table(&[
+    (1, 2),
+    (2, 1),
+    (-1, -2),
+    (-2, -1),
+    (1, -2),
+    (-2, 1),
+    (-1, 2),
+    (2, -1),
+]);
+
+static KING_ATTACKS: [Bitboard; 64] = step_attacks_table(&[
+    (0, 1),
+    (0, -1),
+    (1, 0),
+    (-1, 0),
+    (1, 1),
+    (1, -1),
+    (-1, 1),
+    (-1, -1),
+]);
+
+/// Indexed by the owner of the pawn
+static PAWN_ATTACKS: [[Bitboard; 64]; 2] = [
+    step_attacks_table(&[(1, 1), (1, -1)]),
+    step_attacks_table(&[(-1, 1), (-1, -1)]),
+];
+
+/// The first 4 directions increase the index of the squares, the last 4 decrease it
+const DIRECTIONS: [(i8, i8); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (1, -1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (-1, 1),
+];
+
+const ROOK_DIRECTIONS: [usize; 4] = [0, 2, 4, 6];
+const BISHOP_DIRECTIONS: [usize; 4] = [1, 3, 5, 7];
+
+/// Squares reachable from a square in a direction, on an empty board
+const fn generate_rays() -> [[Bitboard; 64]; 8] {
+    let mut rays = [[0; 64]; 8];
+    let mut direction = 0;
+    while direction < 8 {
+        let (delta_row, delta_col) = DIRECTIONS[direction];
+        let mut index = 0;
+        while index < 64 {
+            let mut row = (index / 8) as i8 + delta_row;
+            let mut col = (index % 8) as i8 + delta_col;
+            while row >= 0 && row < 8 && col >= 0 && col < 8 {
+                rays[direction][index] |= 1 << (row * 8 + col);
+                row += delta_row;
+                col += delta_col;
+            }
+            index += 1;
+        }
+        direction += 1;
+    }
+    rays
+}
+
+/// For every pair of squares on the same line, the squares strictly between them,
+/// and the whole line passing through them
+const fn generate_lines() -> ([[Bitboard; 64]; 64], [[Bitboard; 64]; 64]) {
+    let rays = generate_rays();
+    let mut between = [[0; 64]; 64];
+    let mut lines = [[0; 64]; 64];
+    let mut first = 0;
+    while first < 64 {
+        let mut direction = 0;
+        while direction < 8 {
+            let opposite = (direction + 4) % 8;
+            let mut second = 0;
+            while second < 64 {
+                if rays[direction][first] & (1 << second) != 0 {
+                    between[first][second] = rays[direction][first] & rays[opposite][second];
+                    lines[first][second] =
+                        rays[direction][first] | rays[opposite][first] | (1 << first);
+                }
+                second += 1;
+            }
+            direction += 1;
+        }
+        first += 1;
+    }
+    (between, lines)
+}
+
+static BETWEEN_AND_LINES: ([[Bitboard; 64]; 64], [[Bitboard; 64]; 64]) = generate_lines();
+
+/// Squares strictly between the two positions if they are on the same line, otherwise empty
+#[inline]
+pub fn between(first: Position, second: Position) -> Bitboard {
+    BETWEEN_AND_LINES.0[first.as_usize()][second.as_usize()]
+}
+
+/// The whole line (rank, file or diagonal) passing through both positions, otherwise empty
+#[inline]
+pub fn line(first: Position, second: Position) -> Bitboard {
+    BETWEEN_AND_LINES.1[first.as_usize()][second.as_usize()]
+}
+
+#[inline]
+pub fn knight_attacks(position: Position) -> Bitboard {
+    // SAFETY: Position is always valid
+    unsafe { *KNIGHT_ATTACKS.get_unchecked(position.as_usize()) }
+}
+
+#[inline]
+pub fn king_attacks(position: Position) -> Bitboard {
+    // SAFETY: Position is always valid
+    unsafe { *KING_ATTACKS.get_unchecked(position.as_usize()) }
+}
+
+/// Squares attacked by a pawn of the player placed on position
+#[inline]
+pub fn pawn_attacks(position: Position, player: Players) -> Bitboard {
+    // SAFETY: Position is always valid
+    unsafe { *PAWN_ATTACKS[player.as_usize()].get_unchecked(position.as_usize()) }
+}
+
+#[inline]
+pub fn rook_attacks(position: Position, occupied: Bitboard) -> Bitboard {
+    magic_attacks(&ROOK_TABLE.0, &ROOK_TABLE.1, position, occupied)
+}
+
+#[inline]
+pub fn bishop_attacks(position: Position, occupied: Bitboard) -> Bitboard {
+    magic_attacks(&BISHOP_TABLE.0, &BISHOP_TABLE.1, position, occupied)
+}
+
+#[inline]
+pub fn queen_attacks(position: Position, occupied: Bitboard) -> Bitboard {
+    rook_attacks(position, occupied) | bishop_attacks(position, occupied)
+}
+
+#[inline]
+fn magic_attacks(
+    magics: &[Magic; 64],
+    attacks: &[Bitboard],
+    position: Position,
+    occupied: Bitboard,
+) -> Bitboard {
+    // SAFETY: Position is always valid, and the indices of the magics are inside the table
+    unsafe {
+        let magic = magics.get_unchecked(position.as_usize());
+        *attacks.get_unchecked(magic.index(occupied))
+    }
+}
+
+// Magic bitboards: the occupied squares which can block a slider are multiplied by
+// a magic number, whose top bits then index a table of precomputed attacks
+//
+// Source: https://www.chessprogramming.org/Magic_Bitboards
+
+/// Found by trying sparse pseudorandom numbers until all the occupancies of a square
+/// map to indices holding the same attacks
+const ROOK_MAGICS: [u64; 64] = [
+    0x2080002080400010,
+    0x00C0002001401000,
+    0x2100110008402002,
+    0x0880080081041000,
+    0x0200020020041008,
+    0x2300040008010012,
+    0x0C00283004008201,
+    0x0180010000407A80,
+    0x0168800080400020,
+    0x0010400040201000,
+    0x1001002001001048,
+    0x1001002408100100,
+    0x0801000408010012,
+    0x4001000209000400,
+    0x08A20004C8020001,
+    0x2002801145002280,
+    0x0080860021004200,
+    0x001000C009402002,
+    0x00B0002004002800,
+    0x100A808010020800,
+    0x8101010008000410,
+    0x0244008002000480,
+    0x0000040010810208,
+    0x2000020000448534,
+    0x4104400480008033,
+    0x0000810100204000,
+    0x0440430900200010,
+    0x4600240900100100,
+    0x0060080080040080,
+    0x0001000300080400,
+    0x0004084400011002,
+    0x0023040200008041,
+    0x0580050043002080,
+    0x0400804002802008,
+    0x0001002001004010,
+    0x1000200901001000,
+    0x4410800801800C00,
+    0xA012003806001004,
+    0x0020100104008802,
+    0x0004808402000041,
+    0x0010400170898000,
+    0x0080500020004004,
+    0x1040408012020020,
+    0x8010040008004040,
+    0x2001080100110004,
+    0x0000020004008080,
+    0x0021010810040002,
+    0x0800008C43020024,
+    0x0000800021005100,
+    0x0070201040008080,
+    0x0000D04282006A00,
+    0x0010014400080240,
+    0x0001080110050100,
+    0x0012000810240600,
+    0x0402000801040200,
+    0x028100108A004100,
+    0x0050800300102045,
+    0x8208210040120882,
+    0x8010600101183441,
+    0x020B000910006045,
+    0x0241001002480005,
+    0x0081000400880241,
+    0x0000009008024124,
+    0x0048122980410402,
+];
+
+const BISHOP_MAGICS: [u64; 64] = [
+    0x0848020822040013,
+    0x8010A40085821200,
+    0x0008008430840822,
+    0x0808048108040000,
+    0x1304042100008104,
+    0x5001012010204023,
+    0x81048801B8200420,
+    0x200A008084012000,
+    0x0040102001042084,
+    0x840A505042428020,
+    0x0000700102202920,
+    0x44101C0C10800002,
+    0x0040040422000000,
+    0x0180020802090202,
+    0x4020020811041202,
+    0x000104308C042000,
+    0x4140661002424400,
+    0x0028012008010460,
+    0x0188062102002A00,
+    0x0014004840102008,
+    0x0105000290400002,
+    0x8001022200410400,
+    0x104A041918013446,
+    0x008A000082008238,
+    0x04A0060008100430,
+    0x0008220008820801,
+    0x2508041208005010,
+    0x4008080200202020,
+    0x2441001013004000,
+    0x0030008060407000,
+    0x4008108000420800,
+    0x0012021050290100,
+    0x0210080482200500,
+    0xCC01112048100480,
+    0x0020402806500440,
+    0x00048E0080580080,
+    0x0040102020020080,
+    0x0028010440080807,
+    0x4601041108008800,
+    0x8040810E04104200,
+    0x901210110400088A,
+    0xA003080212081050,
+    0x00C1004048401004,
+    0x900000A014400800,
+    0x0008021040405401,
+    0x4020008206002090,
+    0x0004190424030100,
+    0x0424008A02026250,
+    0x8004088250900040,
+    0x1C00430088A04200,
+    0x0001020094040001,
+    0x8040210020880061,
+    0x2010040450442032,
+    0x0800840850044001,
+    0x0004040802140004,
+    0x0004080A04222020,
+    0x8088802110022000,
+    0x1081A10416114400,
+    0x0205010A24060820,
+    0x0000000720411080,
+    0x1008000208430400,
+    0x580C026028810840,
+    0x802020441020A110,
+    0x12C0022401020018,
+];
+
+/// Sum over all squares of the number of relevant occupancies
+const ROOK_TABLE_SIZE: usize = 102400;
+const BISHOP_TABLE_SIZE: usize = 5248;
+
+#[derive(Clone, Copy)]
+struct Magic {
+    /// Squares whose occupancy changes the attacks, the edges of the board excluded
+    mask: Bitboard,
+    magic: u64,
+    shift: u32,
+    /// Start of the attacks of the square in the table
+    offset: usize,
+}
+
+impl Magic {
+    #[inline]
+    const fn index(&self, occupied: Bitboard) -> usize {
+        self.offset + ((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+/// Squares attacked from index in the given directions, up to and including
+/// the first occupied square, or excluding the last square of every ray if for_mask
+const fn sliding_attacks(
+    index: usize,
+    directions: &[usize; 4],
+    occupied: Bitboard,
+    for_mask: bool,
+) -> Bitboard {
+    let mut attacks = 0;
+    let mut i = 0;
+    while i < 4 {
+        let (delta_row, delta_col) = DIRECTIONS[directions[i]];
+        let mut row = (index / 8) as i8 + delta_row;
+        let mut col = (index % 8) as i8 + delta_col;
+        while row >= 0 && row < 8 && col >= 0 && col < 8 {
+            let (next_row, next_col) = (row + delta_row, col + delta_col);
+            if for_mask && !(next_row >= 0 && next_row < 8 && next_col >= 0 && next_col < 8) {
+                break;
+            }
+            let square: Bitboard = 1 << (row * 8 + col);
+            attacks |= square;
+            if occupied & square != 0 {
+                break;
+            }
+            row = next_row;
+            col = next_col;
+        }
+        i += 1;
+    }
+    attacks
+}
+
+const fn generate_magic_table<const SIZE: usize>(
+    magic_numbers: &[u64; 64],
+    directions: &[usize; 4],
+) -> ([Magic; 64], [Bitboard; SIZE]) {
+    let mut magics = [Magic {
+        mask: 0,
+        magic: 0,
+        shift: 0,
+        offset: 0,
+    }; 64];
+    let mut attacks = [0; SIZE];
+    let mut offset = 0;
+    let mut index = 0;
+    while index < 64 {
+        let mask = sliding_attacks(index, directions, 0, true);
+        let magic = Magic {
+            mask,
+            magic: magic_numbers[index],
+            shift: 64 - mask.count_ones(),
+            offset,
+        };
+
+        // Go through all the subsets of the mask
+        let mut occupied: Bitboard = 0;
+        loop {
+            let entry = &mut attacks[magic.index(occupied)];
+            let square_attacks = sliding_attacks(index, directions, occupied, false);
+            assert!(
+                *entry == 0 || *entry == square_attacks,
+                "Invalid magic number"
+            );
+            *entry = square_attacks;
+
+            occupied = occupied.wrapping_sub(mask) & mask;
+            if occupied == 0 {
+                break;
+            }
+        }
+
+        magics[index] = magic;
+        offset += 1 << mask.count_ones();
+        index += 1;
+    }
+    assert!(offset == SIZE, "Invalid magic table size");
+    (magics, attacks)
+}
+
+static ROOK_TABLE: ([Magic; 64], [Bitboard; ROOK_TABLE_SIZE]) =
+    generate_magic_table(&ROOK_MAGICS, &ROOK_DIRECTIONS);
+
+static BISHOP_TABLE: ([Magic; 64], [Bitboard; BISHOP_TABLE_SIZE]) =
+    generate_magic_table(&BISHOP_MAGICS, &BISHOP_DIRECTIONS);
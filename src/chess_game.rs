@@ -2,13 +2,18 @@ use std::cell::Cell;
 
 use anyhow::{bail, Context};
 use arrayvec::ArrayVec;
-use seq_macro::seq;
 
+use crate::bitboard::{self, Bitboard};
 use crate::gamestate::GameState;
 use crate::move_struct::Move;
+use crate::pgn;
 use crate::piece::{Piece, PieceTypes, Score};
 use crate::position::Position;
 use crate::scores::{self, ENDGAME_THRESHOLD};
+use crate::zobrist;
+
+/// Maximum number of plies played in a game, leaving room in the state stack for the search
+pub const MAX_GAME_LENGTH: usize = 384;
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum GamePhase {
@@ -17,6 +22,16 @@ pub enum GamePhase {
     Endgame,
 }
 
+/// In the order of the bitboards of the pieces, i.e. of their indices
+const PIECE_TYPES: [PieceTypes; 6] = [
+    PieceTypes::Queen,
+    PieceTypes::Rook,
+    PieceTypes::Bishop,
+    PieceTypes::Knight,
+    PieceTypes::Pawn,
+    PieceTypes::King,
+];
+
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Players {
     White = 1,
@@ -29,7 +44,10 @@ pub struct ChessGame {
     pub current_player: Players,
     pub move_stack: Vec<Move>,
     pub phase: GamePhase,
-    board: [Option<Piece>; 64],
+    /// Bitboards of the pieces of each type, indexed by PieceTypes
+    pieces: [Bitboard; 6],
+    /// Bitboards of the pieces of each player, indexed by Players::as_usize
+    players: [Bitboard; 2],
     past_scores: [Score; 64],
     /// Cells are used here in order to allow the changing of the scores
     /// depending on the game's state, e.g. for the endgame
@@ -38,6 +56,13 @@ pub struct ChessGame {
     piece_scores: [Cell<&'static [i16; 64]>; 6],
     king_positions: [Position; 2],
     state: ArrayVec<GameState, 512>,
+    /// Zobrist hash of the position, updated incrementally by push and pop
+    hash: u64,
+    /// Hashes of all the positions of the game, including the current one,
+    /// in the same order as the states
+    hashes: ArrayVec<u64, 512>,
+    /// Fullmove number of the position the game was created from
+    starting_fullmove: u16,
 }
 
 impl Players {
@@ -47,6 +72,15 @@ impl Players {
             Self::Black => Self::White,
         }
     }
+
+    /// Index of the player in arrays holding data for both players
+    #[inline]
+    pub fn as_usize(self) -> usize {
+        match self {
+            Self::White => 0,
+            Self::Black => 1,
+        }
+    }
 }
 
 impl Default for ChessGame {
@@ -59,7 +93,8 @@ impl ChessGame {
     pub fn new(fen: &str) -> anyhow::Result<Self> {
         let mut terms = fen.split_ascii_whitespace();
 
-        let mut board = [None; 64];
+        let mut piece_bitboards = [0; 6];
+        let mut player_bitboards = [0; 2];
         let mut past_scores = [0; 64];
         let mut white_king_pos = None;
         let mut black_king_pos = None;
@@ -99,7 +134,8 @@ impl ChessGame {
                         }
                     }
                     let position = Position::new_assert(row, col);
-                    board[position.as_usize()] = Some(piece);
+                    piece_bitboards[piece.piece_type as usize] |= bitboard::square(position);
+                    player_bitboards[piece.owner.as_usize()] |= bitboard::square(position);
                     past_scores[position.as_usize()] = piece.score(position, &piece_scores);
                     col += 1;
                 }
@@ -149,6 +185,22 @@ impl ChessGame {
             }
         }
 
+        // The move counters are optional, since they are often omitted
+        if let Some(halfmove_clock) = terms.next() {
+            let halfmove_clock = halfmove_clock
+                .parse()
+                .with_context(|| "Invalid halfmove clock")?;
+            state.set_halfmove_clock(halfmove_clock);
+        }
+
+        let starting_fullmove = match terms.next() {
+            Some(fullmove) => match fullmove.parse() {
+                Ok(fullmove) if fullmove >= 1 => fullmove,
+                _ => bail!("Invalid fullmove number"),
+            },
+            None => 1,
+        };
+
         let Some(white_king_pos) = white_king_pos else {
             bail!("White king not found");
         };
@@ -158,18 +210,29 @@ impl ChessGame {
         };
 
         let mut game = Self {
-            board,
+            pieces: piece_bitboards,
+            players: player_bitboards,
             move_stack: Vec::with_capacity(1000),
             king_positions: [white_king_pos, black_king_pos],
             current_player,
-            score: 0,
+            score: past_scores.iter().sum(),
             state: ArrayVec::new(),
             past_scores,
             piece_scores,
             phase: GamePhase::Opening,
+            hash: 0,
+            hashes: ArrayVec::new(),
+            starting_fullmove,
         };
 
+        if state.en_passant() < 8
+            && game.can_capture_en_passant(state.en_passant(), game.current_player)
+        {
+            state.set_en_passant_capturable();
+        }
         game.state.push(state);
+        game.hash = game.compute_hash();
+        game.hashes.push(game.hash);
         game.update_phase();
 
         Ok(game)
@@ -179,31 +242,180 @@ impl ChessGame {
         self.state.len()
     }
 
+    /// Zobrist hash of the current position
+    /// Covers the board, the current player, castling rights and en passant
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Whether a pawn of the player could capture en passant on the file, pins aside,
+    /// which is when the en passant square is part of the hash
+    fn can_capture_en_passant(&self, file: i8, player: Players) -> bool {
+        let row = match player {
+            Players::White => 5,
+            Players::Black => 2,
+        };
+        // The pawns which could capture on the square are the ones
+        // an enemy pawn placed on that square would attack
+        bitboard::pawn_attacks(Position::new_assert(row, file), player.the_other())
+            & self.piece_bitboard(PieceTypes::Pawn, player)
+            != 0
+    }
+
+    /// Computes the hash from scratch, as opposed to the incrementally updated one
+    fn compute_hash(&self) -> u64 {
+        let mut hash = zobrist::state_key(self.state());
+        if self.current_player == Players::Black {
+            hash ^= zobrist::player_key();
+        }
+
+        for row in 0..8 {
+            for col in 0..8 {
+                let position = Position::new_assert(row, col);
+                if let Some(piece) = self.get_position(position) {
+                    hash ^= zobrist::piece_key(piece, position);
+                }
+            }
+        }
+
+        hash
+    }
+
+    /// Number of the current move, starting at 1 and incremented after every black move
+    pub fn fullmove_number(&self) -> usize {
+        let plies = self.len() - 1;
+        let starting_player_is_black =
+            (self.current_player == Players::Black) == plies.is_multiple_of(2);
+        self.starting_fullmove as usize + (plies + starting_player_is_black as usize) / 2
+    }
+
+    /// Forsyth-Edwards Notation of the current position, the inverse of ChessGame::new
+    ///
+    /// Source: https://www.chessprogramming.org/Forsyth-Edwards_Notation
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+
+        for row in (0..8).rev() {
+            let mut empty_count = 0;
+            for col in 0..8 {
+                match self.get_position(Position::new_assert(row, col)) {
+                    Some(piece) => {
+                        if empty_count > 0 {
+                            fen.push((b'0' + empty_count) as char);
+                            empty_count = 0;
+                        }
+                        fen.push(piece.as_fen_char());
+                    }
+                    None => empty_count += 1,
+                }
+            }
+            if empty_count > 0 {
+                fen.push((b'0' + empty_count) as char);
+            }
+            if row > 0 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(match self.current_player {
+            Players::White => " w ",
+            Players::Black => " b ",
+        });
+
+        let state = self.state();
+        for (has_right, right) in [
+            (state.white_king_castling(), 'K'),
+            (state.white_queen_castling(), 'Q'),
+            (state.black_king_castling(), 'k'),
+            (state.black_queen_castling(), 'q'),
+        ] {
+            if has_right {
+                fen.push(right);
+            }
+        }
+        if state.castling_rights() == 0 {
+            fen.push('-');
+        }
+
+        fen.push(' ');
+        if state.en_passant() < 8 {
+            fen.push((b'a' + state.en_passant() as u8) as char);
+            // The square behind the pawn which just moved two squares
+            fen.push(match self.current_player {
+                Players::White => '6',
+                Players::Black => '3',
+            });
+        } else {
+            fen.push('-');
+        }
+
+        fen.push_str(&format!(
+            " {} {}",
+            state.halfmove_clock(),
+            self.fullmove_number()
+        ));
+
+        fen
+    }
+
+    /// Looked up in the bitboards, so that push and pop don't have to keep a board of pieces
     pub fn get_position(&self, position: Position) -> Option<Piece> {
-        // SAFETY: position is always valid
-        unsafe { *self.board.get_unchecked(position.as_usize()) }
+        let square = bitboard::square(position);
+        let owner = if self.players[Players::White.as_usize()] & square != 0 {
+            Players::White
+        } else if self.players[Players::Black.as_usize()] & square != 0 {
+            Players::Black
+        } else {
+            return None;
+        };
+
+        let piece_type = *PIECE_TYPES
+            .iter()
+            .find(|&&piece_type| self.pieces[piece_type as usize] & square != 0)?;
+
+        Some(Piece { piece_type, owner })
     }
 
     fn set_position(&mut self, position: Position, new_place: Option<Piece>) {
+        let place = self.get_position(position);
         // SAFETY: position is always valid
-        let (place, place_score) = unsafe {
-            (
-                self.board.get_unchecked_mut(position.as_usize()),
-                self.past_scores.get_unchecked_mut(position.as_usize()),
-            )
-        };
+        let place_score = unsafe { self.past_scores.get_unchecked_mut(position.as_usize()) };
 
         self.score -= *place_score;
 
-        *place = new_place;
+        if let Some(piece) = place {
+            self.hash ^= zobrist::piece_key(piece, position);
+            self.pieces[piece.piece_type as usize] ^= bitboard::square(position);
+            self.players[piece.owner.as_usize()] ^= bitboard::square(position);
+        }
+        if let Some(piece) = new_place {
+            self.hash ^= zobrist::piece_key(piece, position);
+            self.pieces[piece.piece_type as usize] ^= bitboard::square(position);
+            self.players[piece.owner.as_usize()] ^= bitboard::square(position);
+        }
 
-        *place_score = place
+        *place_score = new_place
             .map(|piece| piece.score(position, &self.piece_scores))
             .unwrap_or(0);
 
         self.score += *place_score;
     }
 
+    #[inline]
+    pub fn piece_bitboard(&self, piece_type: PieceTypes, player: Players) -> Bitboard {
+        self.pieces[piece_type as usize] & self.players[player.as_usize()]
+    }
+
+    #[inline]
+    pub fn player_bitboard(&self, player: Players) -> Bitboard {
+        self.players[player.as_usize()]
+    }
+
+    #[inline]
+    pub fn occupied_bitboard(&self) -> Bitboard {
+        self.players[0] | self.players[1]
+    }
+
     pub fn get_king_position(&self, player: Players) -> Position {
         match player {
             Players::White => self.king_positions[0],
@@ -223,6 +435,46 @@ impl ChessGame {
         unsafe { *self.state.last().unwrap_unchecked() }
     }
 
+    /// Number of times the current position occurred before in the game
+    ///
+    /// Only positions since the last capture or pawn move are checked,
+    /// as the earlier ones can't be repeated anymore, and none before a null move,
+    /// since passing the turn isn't a move which can repeat a position
+    pub fn repetitions(&self) -> usize {
+        self.repetitions_within(usize::MAX)
+    }
+
+    /// Number of times the current position occurred in the last plies of the game
+    pub fn repetitions_within(&self, plies: usize) -> usize {
+        let current = self.hashes.len() - 1;
+        let state = self.state();
+        let plies = plies.min(state.halfmove_clock().min(state.plies_from_null()) as usize);
+        let first = current.saturating_sub(plies);
+        // Positions with the other player to move can't be equal
+        self.hashes[first..current]
+            .iter()
+            .rev()
+            .skip(1)
+            .step_by(2)
+            .filter(|&&hash| hash == self.hash)
+            .count()
+    }
+
+    /// The game is drawn after 100 plies without captures or pawn moves,
+    /// unless the last of them delivered checkmate
+    pub fn is_fifty_move_draw(&self) -> bool {
+        if self.state().halfmove_clock() < 100 {
+            return false;
+        }
+        if !self.is_in_check() {
+            return true;
+        }
+
+        let mut moves = ArrayVec::new();
+        self.get_evasions(&mut moves);
+        !moves.is_empty()
+    }
+
     pub fn push_history(&mut self, _move: Move) {
         self.move_stack.push(_move);
         self.update_phase();
@@ -232,6 +484,23 @@ impl ChessGame {
     pub fn push(&mut self, _move: Move) {
         let mut state = self.state();
         state.set_en_passant(8);
+        state.set_plies_from_null(state.plies_from_null().saturating_add(1));
+
+        let resets_halfmove_clock = match _move {
+            Move::Normal {
+                piece,
+                captured_piece,
+                ..
+            } => piece.piece_type == PieceTypes::Pawn || captured_piece.is_some(),
+            Move::Promotion { .. } | Move::EnPassant { .. } => true,
+            Move::CastlingShort { .. } | Move::CastlingLong { .. } => false,
+        };
+        if resets_halfmove_clock {
+            state.set_halfmove_clock(0);
+        } else {
+            state.set_halfmove_clock(state.halfmove_clock().saturating_add(1));
+        }
+
         match _move {
             Move::Normal {
                 piece,
@@ -285,6 +554,9 @@ impl ChessGame {
 
                 if piece.piece_type == PieceTypes::Pawn && i8::abs(end.row() - start.row()) == 2 {
                     state.set_en_passant(start.col());
+                    if self.can_capture_en_passant(start.col(), self.current_player.the_other()) {
+                        state.set_en_passant_capturable();
+                    }
                 }
             }
             Move::Promotion {
@@ -433,20 +705,52 @@ impl ChessGame {
                 }
             }
         };
+        self.push_state(state);
+    }
+
+    /// Passes the turn to the other player without moving, which isn't legal,
+    /// but tells the search how good the position is even when doing nothing
+    ///
+    /// Must not be made while in check, and is undone with pop_null
+    pub fn push_null(&mut self) {
+        let mut state = self.state();
+        state.set_en_passant(8);
+        state.set_halfmove_clock(state.halfmove_clock().saturating_add(1));
+        state.set_plies_from_null(0);
+        self.push_state(state);
+    }
+
+    pub fn pop_null(&mut self) {
+        self.pop_state();
+    }
+
+    /// Ends the turn of the current player, with the state after their move
+    fn push_state(&mut self, state: GameState) {
         self.current_player = self.current_player.the_other();
+        self.hash ^= zobrist::state_key(self.state()) ^ zobrist::state_key(state);
+        self.hash ^= zobrist::player_key();
         // SAFETY: The game will not be longer than 512 moves
         unsafe {
             self.state.push_unchecked(state);
+            self.hashes.push_unchecked(self.hash);
         }
     }
 
-    pub fn pop(&mut self, _move: Move) {
+    fn pop_state(&mut self) {
+        let old_state = self.state();
         // SAFETY: There is always a previous state
         unsafe {
             // self.state.pop() without verification for being empty
             self.state.set_len(self.len() - 1);
+            self.hashes.set_len(self.len());
         }
         self.current_player = self.current_player.the_other();
+        self.hash ^= zobrist::state_key(old_state) ^ zobrist::state_key(self.state());
+        self.hash ^= zobrist::player_key();
+    }
+
+    pub fn pop(&mut self, _move: Move) {
+        self.pop_state();
 
         match _move {
             Move::Normal {
@@ -580,16 +884,11 @@ impl ChessGame {
     }
 
     fn is_endgame(&self) -> bool {
-        let mut total_piece_score: u32 = 0;
-
-        for row in 0..8 {
-            for col in 0..8 {
-                let position = Position::new_assert(row, col);
-                if let Some(piece) = self.get_position(position) {
-                    total_piece_score += piece.score(position, &self.piece_scores).abs() as u32;
-                }
-            }
-        }
+        // The scores stored when the pieces were placed are the current ones,
+        // since the scores only change once the endgame is reached
+        let total_piece_score: u32 = bitboard::squares(self.occupied_bitboard())
+            .map(|position| self.past_scores[position.as_usize()].unsigned_abs() as u32)
+            .sum();
 
         total_piece_score < 2 * ENDGAME_THRESHOLD // because we are counting both sides
     }
@@ -601,237 +900,289 @@ impl ChessGame {
         }
     }
 
-    pub fn king_exists(&self, player: Players) -> bool {
-        self.get_position(self.get_king_position(player))
-            .is_some_and(|piece| piece.piece_type == PieceTypes::King)
+    /// The position the game was created from, before the moves of the move stack
+    pub fn starting_position(&self) -> ChessGame {
+        let mut game = self.clone();
+        for _move in self.move_stack.iter().rev() {
+            game.pop(*_move);
+        }
+        game.move_stack.clear();
+        game
     }
 
-    /// `moves` will be cleared by this function to be sure it has room for all moves
-    pub fn get_moves(&mut self, moves: &mut ArrayVec<Move, 256>, verify_king: bool) {
-        moves.clear();
+    /// Movetext of the game, in Standard Algebraic Notation
+    pub fn get_pgn(&self) -> String {
+        let mut s = String::new();
+        for word in pgn::movetext(self, &[]) {
+            s.push_str(&word);
+            s.push(' ');
+        }
+        s
+    }
+}
+impl std::fmt::Display for ChessGame {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f)?;
+
+        for i in (0..8).rev() {
+            write!(f, "{} ", i + 1)?;
+            for j in 0..8 {
+                let position = Position::new_assert(i, j);
+                write!(
+                    f,
+                    "|{}",
+                    self.get_position(position)
+                        .map(|piece| piece.as_char())
+                        .unwrap_or(' ')
+                )?;
+            }
+            writeln!(f, "|")?;
+        }
+        writeln!(f, "\n   a b c d e f g h")
+    }
+}
 
-        if !self.king_exists(self.current_player) {
-            // no available moves;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Walks the game tree and verifies the incremental hash against a fresh one
+    fn verify_hash(game: &mut ChessGame, depth: u8) {
+        assert_eq!(game.hash(), game.compute_hash());
+        if depth == 0 {
             return;
         }
 
-        let mut push = |_move| {
-            // SAFETY: The number of possible moves on the board at any given time
-            // will never exceed the arrays capacity (256)
-            unsafe {
-                moves.push_unchecked(_move);
-            }
-        };
+        let mut moves = ArrayVec::new();
+        game.get_moves(&mut moves);
+        for _move in moves {
+            game.push(_move);
+            verify_hash(game, depth - 1);
+            game.pop(_move);
+            assert_eq!(game.hash(), game.compute_hash());
+        }
+    }
 
-        seq!(row in 0..8 {
-            seq!(col in 0..8 {
-                let pos = Position::new_assert(row, col);
-                if let Some(piece) = self.get_position(pos) {
-                    if piece.owner == self.current_player {
-                        piece.get_moves(&mut push, self, pos);
-                    }
-                }
-            });
-        });
+    #[test]
+    fn incremental_hash_kiwipete() {
+        let mut game =
+            ChessGame::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -")
+                .unwrap();
+        verify_hash(&mut game, 3);
+    }
 
-        // If verify_king then remove moves which put the king in check (invalid moves)
-        // We remove invalid moves by overwriting them with the following valid moves
-        if verify_king {
-            let player = self.current_player;
-            let king_position = self.get_king_position(player);
-            let is_king_targeted = self.is_targeted(king_position, player);
-            let mut keep_index = 0;
-            for index in 0..moves.len() {
-                let _move = moves[index];
-
-                if !is_king_targeted {
-                    if let Move::Normal { start, .. } = _move {
-                        let delta_col = start.col() - king_position.col();
-                        let delta_row = start.row() - king_position.row();
-                        if delta_col != 0 && delta_row != 0 && delta_col.abs() != delta_row.abs() {
-                            moves[keep_index] = _move;
-                            keep_index += 1;
-                            continue;
-                        }
-                    }
-                }
+    #[test]
+    fn incremental_hash_position_3() {
+        let mut game = ChessGame::new("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - ").unwrap();
+        verify_hash(&mut game, 4);
+    }
 
-                self.push(_move);
-                let condition = !self.is_targeted(self.get_king_position(player), player);
-                self.pop(_move);
-                if condition {
-                    moves[keep_index] = _move;
-                    keep_index += 1;
-                }
-            }
-            unsafe { moves.set_len(keep_index) };
+    /// Positions of the perft test suite, with all six FEN fields
+    const PERFT_FENS: [&str; 14] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        "3k4/3p4/8/K1P4r/8/8/8/8 b - - 0 1",
+        "8/8/4k3/8/2p5/8/B2P2K1/8 w - - 0 1",
+        "8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1",
+        "5k2/8/8/8/8/8/8/4K2R w K - 0 1",
+        "r3k2r/8/3Q4/8/8/5q2/8/R3K2R b KQkq - 0 1",
+        "2K2r2/4P3/8/8/8/8/8/3k4 w - - 0 1",
+        "8/8/1P2K3/8/2n5/1q6/8/5k2 b - - 0 1",
+        "8/k1P5/8/1K6/8/8/8/8 w - - 0 1",
+    ];
+
+    /// Walks the game tree and verifies that every position survives a FEN round trip
+    fn verify_fen(game: &mut ChessGame, depth: u8) {
+        let fen = game.to_fen();
+        assert_eq!(ChessGame::new(&fen).unwrap().to_fen(), fen);
+        if depth == 0 {
+            return;
+        }
+
+        let mut moves = ArrayVec::new();
+        game.get_moves(&mut moves);
+        for _move in moves {
+            game.push(_move);
+            verify_fen(game, depth - 1);
+            game.pop(_move);
+            assert_eq!(game.to_fen(), fen);
         }
     }
 
-    /// Returns if player's position is targeted by enemy pieces
-    ///
-    /// This function is ONLY used for testing castling rights and if a king is in check
-    ///
-    /// Thus I considered it unnecessary to verify if the square is targeted by a king,
-    /// since I already verify that moves don't put kings near each other and a king blocking
-    /// a castling move is so unlikely I don't want to waste time on it.
-    pub fn is_targeted(&self, position: Position, player: Players) -> bool {
-        // Verifiy for kings
-        for delta in [
-            (1, 0),
-            (0, 1),
-            (-1, 0),
-            (0, -1),
-            (1, 1),
-            (-1, 1),
-            (1, -1),
-            (-1, -1),
-        ] {
-            if let Some(new_pos) = position.add(delta) {
-                if self.get_position(new_pos).is_some_and(|piece| {
-                    piece.owner != player && piece.piece_type == PieceTypes::King
-                }) {
-                    return true;
-                }
-            }
+    #[test]
+    fn fen_round_trip() {
+        for fen in PERFT_FENS {
+            let mut game = ChessGame::new(fen).unwrap();
+            assert_eq!(game.to_fen(), fen);
+            verify_fen(&mut game, 2);
         }
+    }
 
-        // Verify for knights
-        for delta in [
-            (1, 2),
-            (2, 1),
-            (-1, -2),
-            (-2, -1),
-            (1, -2),
-            (-2, 1),
-            (-1, 2),
-            (2, -1),
+    #[test]
+    fn fen_move_counters() {
+        let mut game = ChessGame::default();
+        for (move_str, fen) in [
+            (
+                "e2e4",
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            ),
+            (
+                "c7c5",
+                "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2",
+            ),
+            (
+                "g1f3",
+                "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2",
+            ),
+            (
+                "b8c6",
+                "r1bqkbnr/pp1ppppp/2n5/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+            ),
         ] {
-            if let Some(new_pos) = position.add(delta) {
-                if self.get_position(new_pos).is_some_and(|piece| {
-                    piece.owner != player && piece.piece_type == PieceTypes::Knight
-                }) {
-                    return true;
-                }
-            }
+            game.push(Move::from_uci_notation(move_str, &game).unwrap());
+            assert_eq!(game.to_fen(), fen);
         }
 
-        // Verify for pawns
-        match player {
-            Players::White => {
-                if let Some(new_pos) = position.add((1, 1)) {
-                    if self.get_position(new_pos).is_some_and(|piece| {
-                        piece.owner != player && piece.piece_type == PieceTypes::Pawn
-                    }) {
-                        return true;
-                    }
-                }
-                if let Some(new_pos) = position.add((1, -1)) {
-                    if self.get_position(new_pos).is_some_and(|piece| {
-                        piece.owner != player && piece.piece_type == PieceTypes::Pawn
-                    }) {
-                        return true;
-                    }
-                }
-            }
-            Players::Black => {
-                if let Some(new_pos) = position.add((-1, 1)) {
-                    if self.get_position(new_pos).is_some_and(|piece| {
-                        piece.owner != player && piece.piece_type == PieceTypes::Pawn
-                    }) {
-                        return true;
-                    }
-                }
-                if let Some(new_pos) = position.add((-1, -1)) {
-                    if self.get_position(new_pos).is_some_and(|piece| {
-                        piece.owner != player && piece.piece_type == PieceTypes::Pawn
-                    }) {
-                        return true;
-                    }
-                }
+        let game = ChessGame::new("8/8/8/8/8/8/8/K1k5 w - -").unwrap();
+        assert_eq!(game.to_fen(), "8/8/8/8/8/8/8/K1k5 w - - 0 1");
+    }
+
+    #[test]
+    fn repetitions() {
+        let mut game = ChessGame::default();
+        for count in 0..3 {
+            assert_eq!(game.repetitions(), count);
+            for move_str in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+                game.push(Move::from_uci_notation(move_str, &game).unwrap());
             }
-        };
+        }
+        assert_eq!(game.repetitions(), 3);
+        assert_eq!(game.repetitions_within(3), 0);
+        assert_eq!(game.repetitions_within(4), 1);
+
+        // A pawn move makes the earlier positions unreachable
+        game.push(Move::from_uci_notation("e2e3", &game).unwrap());
+        game.push(Move::from_uci_notation("g8f6", &game).unwrap());
+        game.push(Move::from_uci_notation("g1f3", &game).unwrap());
+        game.push(Move::from_uci_notation("f6g8", &game).unwrap());
+        game.push(Move::from_uci_notation("f3g1", &game).unwrap());
+        assert_eq!(game.repetitions(), 1);
+    }
 
-        // Helpful macro
-        macro_rules! search_enemies_loops {
-            ( $piece_type1:expr, $piece_type2:expr, $( $x:expr ),* ) => {
-                $(
-                for delta in $x {
-                    if let Some(new_pos) = position.add(delta) {
-                        if let Some(piece) = self.get_position(new_pos)  {
-                            if piece.owner != player &&
-                                (piece.piece_type == $piece_type1 || piece.piece_type == $piece_type2) {
-                                return true
-                            }
-                            break;
-                        }
-                    } else {
-                        break;
-                    }
-                }
-                )*
-            };
-        }
-
-        // Verify lines for rooks/queens
-        search_enemies_loops![
-            PieceTypes::Rook,
-            PieceTypes::Queen,
-            (1..).map(|x| (0, x)),
-            (1..).map(|x| (0, -x)),
-            (1..).map(|x| (x, 0)),
-            (1..).map(|x| (-x, 0))
-        ];
+    #[test]
+    fn pgn_move_numbers() {
+        let mut game = ChessGame::default();
+        for move_str in ["e2e4", "e7e5", "g1f3"] {
+            game.push_history(Move::from_uci_notation(move_str, &game).unwrap());
+        }
+        assert_eq!(game.get_pgn(), "1. e4 e5 2. Nf3 ");
 
-        // Verify diagonals for bishops/queens
-        search_enemies_loops![
-            PieceTypes::Bishop,
-            PieceTypes::Queen,
-            (1..).map(|x| (x, x)),
-            (1..).map(|x| (-x, -x)),
-            (1..).map(|x| (x, -x)),
-            (1..).map(|x| (-x, x))
-        ];
+        let mut game =
+            ChessGame::new("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 5").unwrap();
+        for move_str in ["c7c5", "g1f3"] {
+            game.push_history(Move::from_uci_notation(move_str, &game).unwrap());
+        }
+        assert_eq!(game.get_pgn(), "5... c5 6. Nf3 ");
+    }
 
-        false
+    #[test]
+    fn fifty_move_rule() {
+        let mut game = ChessGame::new("k7/8/1K6/8/8/8/8/7R w - - 99 80").unwrap();
+        assert!(!game.is_fifty_move_draw());
+
+        let quiet_move = Move::from_uci_notation("h1h2", &game).unwrap();
+        game.push(quiet_move);
+        assert!(game.is_fifty_move_draw());
+        game.pop(quiet_move);
+
+        // Checkmate on the hundredth ply takes precedence over the draw
+        game.push(Move::from_uci_notation("h1h8", &game).unwrap());
+        assert!(!game.is_fifty_move_draw());
     }
 
-    pub fn get_pgn(&self) -> String {
-        let moves: Vec<_> = self.move_stack.iter().map(Move::pgn_notation).collect();
+    #[test]
+    fn null_move() {
+        let mut game = ChessGame::new("4k3/8/8/3pP3/8/8/8/4K2R w K d6 3 20").unwrap();
+        let fen = game.to_fen();
+        let hash = game.hash();
+
+        game.push_null();
+        assert_eq!(game.to_fen(), "4k3/8/8/3pP3/8/8/8/4K2R b K - 4 20");
+        assert_eq!(game.hash(), game.compute_hash());
+        game.pop_null();
+        assert_eq!(game.to_fen(), fen);
+        assert_eq!(game.hash(), hash);
+    }
 
-        let mut s = String::new();
+    #[test]
+    fn no_repetitions_through_null_moves() {
+        let mut game = ChessGame::default();
+        game.push(Move::from_uci_notation("g1f3", &game).unwrap());
+        game.push_null();
+        game.push(Move::from_uci_notation("f3g1", &game).unwrap());
+        game.push_null();
+
+        // The starting position again, but only because black passed twice
+        assert_eq!(game.hash(), ChessGame::default().hash());
+        assert_eq!(game.repetitions(), 0);
+    }
 
-        for (i, _move) in moves.iter().enumerate() {
-            if i % 2 == 0 {
-                s.push_str((i / 2 + 1).to_string().as_str());
-                s.push('.');
-                s.push(' ');
-            }
-            s.push_str(_move.as_str());
-            s.push(' ');
+    #[test]
+    fn transposition_same_hash() {
+        let mut first = ChessGame::default();
+        let mut second = ChessGame::default();
+        for move_str in ["g1f3", "g8f6", "b1c3", "b8c6"] {
+            first.push(Move::from_uci_notation(move_str, &first).unwrap());
+        }
+        for move_str in ["b1c3", "b8c6", "g1f3", "g8f6"] {
+            second.push(Move::from_uci_notation(move_str, &second).unwrap());
         }
+        assert_eq!(first.hash(), second.hash());
+        assert_ne!(first.hash(), ChessGame::default().hash());
+    }
 
-        s
+    #[test]
+    fn score_from_fen() {
+        let mut game = ChessGame::default();
+        for move_str in ["e2e4", "d7d5", "e4d5", "d8d5", "b1c3"] {
+            game.push(Move::from_uci_notation(move_str, &game).unwrap());
+        }
+        // Starting from the pieces on the board, not from 0
+        let loaded = ChessGame::new(&game.to_fen()).unwrap();
+        assert_eq!(loaded.score, game.score);
+        assert_ne!(loaded.score, 0);
     }
-}
-impl std::fmt::Display for ChessGame {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        writeln!(f)?;
 
-        for i in (0..8).rev() {
-            write!(f, "{} ", i + 1)?;
-            for j in 0..8 {
-                let position = Position::new_assert(i, j);
-                write!(
-                    f,
-                    "|{}",
-                    self.get_position(position)
-                        .map(|piece| piece.as_char())
-                        .unwrap_or(' ')
-                )?;
-            }
-            writeln!(f, "|")?;
+    #[test]
+    fn en_passant_hash() {
+        // No black pawn can capture en passant, so it's the position without the double push
+        let mut game = ChessGame::default();
+        game.push(Move::from_uci_notation("e2e4", &game).unwrap());
+        assert_eq!(
+            game.to_fen(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+        );
+        let without_en_passant =
+            ChessGame::new("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+        assert_eq!(game.hash(), without_en_passant.hash());
+        assert_eq!(game.hash(), ChessGame::new(&game.to_fen()).unwrap().hash());
+
+        // Which doesn't stop it from repeating
+        for move_str in ["g8f6", "g1f3", "f6g8", "f3g1"] {
+            game.push(Move::from_uci_notation(move_str, &game).unwrap());
         }
-        writeln!(f, "\n   a b c d e f g h")
+        assert_eq!(game.repetitions(), 1);
+
+        // Here the pawn on d4 can capture
+        let mut game = ChessGame::new("4k3/8/8/8/3p4/8/4P3/4K3 w - - 0 1").unwrap();
+        game.push(Move::from_uci_notation("e2e4", &game).unwrap());
+        let with_en_passant = ChessGame::new("4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1").unwrap();
+        let without_en_passant = ChessGame::new("4k3/8/8/8/3pP3/8/8/4K3 b - - 0 1").unwrap();
+        assert_eq!(game.hash(), with_en_passant.hash());
+        assert_ne!(game.hash(), without_en_passant.hash());
     }
 }
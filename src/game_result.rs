@@ -0,0 +1,184 @@
+use arrayvec::ArrayVec;
+
+use crate::bitboard::{DARK_SQUARES, LIGHT_SQUARES};
+use crate::chess_game::{ChessGame, Players};
+use crate::piece::PieceTypes;
+
+/// Why a game ended
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Termination {
+    Checkmate,
+    Stalemate,
+    ThreefoldRepetition,
+    FiftyMoveRule,
+    InsufficientMaterial,
+    /// The game was stopped from outside, e.g. because it was getting too long
+    Adjudication,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum GameResult {
+    WhiteWins(Termination),
+    BlackWins(Termination),
+    Draw(Termination),
+}
+
+impl GameResult {
+    pub fn win(winner: Players, termination: Termination) -> Self {
+        match winner {
+            Players::White => Self::WhiteWins(termination),
+            Players::Black => Self::BlackWins(termination),
+        }
+    }
+
+    pub fn termination(self) -> Termination {
+        match self {
+            Self::WhiteWins(termination)
+            | Self::BlackWins(termination)
+            | Self::Draw(termination) => termination,
+        }
+    }
+
+    /// The result as written in the Result tag and at the end of the movetext
+    pub fn pgn_result(self) -> &'static str {
+        match self {
+            Self::WhiteWins(_) => "1-0",
+            Self::BlackWins(_) => "0-1",
+            Self::Draw(_) => "1/2-1/2",
+        }
+    }
+}
+
+impl std::fmt::Display for Termination {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let reason = match self {
+            Self::Checkmate => "checkmate",
+            Self::Stalemate => "stalemate",
+            Self::ThreefoldRepetition => "threefold repetition",
+            Self::FiftyMoveRule => "fifty-move rule",
+            Self::InsufficientMaterial => "insufficient material",
+            Self::Adjudication => "adjudication",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+impl ChessGame {
+    /// Returns the result of the game if it is over by the rules of chess
+    ///
+    /// Adjudication is never returned, it is up to the caller to decide on it
+    pub fn result(&self) -> Option<GameResult> {
+        let mut moves = ArrayVec::new();
+        self.get_moves(&mut moves);
+
+        if moves.is_empty() {
+            if self.is_in_check() {
+                return Some(GameResult::win(
+                    self.current_player.the_other(),
+                    Termination::Checkmate,
+                ));
+            }
+            return Some(GameResult::Draw(Termination::Stalemate));
+        }
+
+        if self.state().halfmove_clock() >= 100 {
+            return Some(GameResult::Draw(Termination::FiftyMoveRule));
+        }
+
+        if self.repetitions() >= 2 {
+            return Some(GameResult::Draw(Termination::ThreefoldRepetition));
+        }
+
+        if self.is_insufficient_material() {
+            return Some(GameResult::Draw(Termination::InsufficientMaterial));
+        }
+
+        None
+    }
+
+    /// Neither player can possibly checkmate, whatever moves are made: only the kings
+    /// are left, with at most a single minor piece, or with bishops all on the same color
+    pub fn is_insufficient_material(&self) -> bool {
+        let pieces = |piece_type| {
+            self.piece_bitboard(piece_type, Players::White)
+                | self.piece_bitboard(piece_type, Players::Black)
+        };
+
+        if pieces(PieceTypes::Pawn) | pieces(PieceTypes::Rook) | pieces(PieceTypes::Queen) != 0 {
+            return false;
+        }
+
+        let knights = pieces(PieceTypes::Knight);
+        let bishops = pieces(PieceTypes::Bishop);
+        if (knights | bishops).count_ones() <= 1 {
+            return true;
+        }
+
+        knights == 0 && (bishops & LIGHT_SQUARES == 0 || bishops & DARK_SQUARES == 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::move_struct::Move;
+
+    #[test]
+    fn game_results() {
+        for (fen, result) in [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                None,
+            ),
+            (
+                "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3",
+                Some(GameResult::BlackWins(Termination::Checkmate)),
+            ),
+            (
+                "k7/8/1Q6/8/8/8/8/7K b - - 0 1",
+                Some(GameResult::Draw(Termination::Stalemate)),
+            ),
+            (
+                "k7/8/8/8/8/8/8/6RK b - - 100 90",
+                Some(GameResult::Draw(Termination::FiftyMoveRule)),
+            ),
+            (
+                "k7/8/8/8/8/8/8/7K w - - 0 1",
+                Some(GameResult::Draw(Termination::InsufficientMaterial)),
+            ),
+        ] {
+            assert_eq!(ChessGame::new(fen).unwrap().result(), result, "{}", fen);
+        }
+
+        let mut game = ChessGame::default();
+        for _ in 0..2 {
+            for move_str in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+                game.push(Move::from_uci_notation(move_str, &game).unwrap());
+            }
+        }
+        assert_eq!(
+            game.result(),
+            Some(GameResult::Draw(Termination::ThreefoldRepetition))
+        );
+    }
+
+    #[test]
+    fn insufficient_material() {
+        for (fen, insufficient) in [
+            ("8/8/3k4/8/8/8/3K4/8 w - -", true),
+            ("8/8/3k4/8/8/8/3K4/5B2 w - -", true),
+            ("8/8/3k4/8/8/8/3K4/5N2 b - -", true),
+            ("8/8/3k2b1/8/8/8/3K4/5B2 w - -", true),
+            ("2b5/8/3k2b1/8/8/8/3KB3/5B2 w - -", true),
+            ("8/8/3k1b2/8/8/8/3K4/5B2 w - -", false),
+            ("8/8/3k2n1/8/8/8/3K4/5N2 w - -", false),
+            ("8/8/3k2n1/8/8/8/3K4/5B2 w - -", false),
+            ("8/8/3k4/8/8/8/3K4/4NN2 w - -", false),
+            ("8/8/3k4/8/8/8/3K2P1/8 w - -", false),
+            ("8/8/3k4/8/8/8/3K4/7R w - -", false),
+        ] {
+            let game = ChessGame::new(fen).unwrap();
+            assert_eq!(game.is_insufficient_material(), insufficient, "{}", fen);
+        }
+    }
+}
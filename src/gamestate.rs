@@ -5,6 +5,12 @@ pub struct GameState {
     /// First 4 bits represent en passant
     /// The last 4 bits of castling_rights indicate castling rights
     bitfield: u8,
+    /// Number of moves since the last capture or pawn move
+    halfmove_clock: u8,
+    /// Number of moves since the last null move, saturating at u8::MAX
+    plies_from_null: u8,
+    /// Whether a pawn can capture on the en passant square
+    en_passant_capturable: bool,
 }
 
 impl GameState {
@@ -13,9 +19,47 @@ impl GameState {
         (self.bitfield & 0b1111) as i8
     }
 
+    /// Also marks the en passant capture as impossible, until set_en_passant_capturable
     #[inline]
     pub fn set_en_passant(&mut self, value: i8) {
         self.bitfield = (self.bitfield & 0b11110000) + (value as u8);
+        self.en_passant_capturable = false;
+    }
+
+    #[inline]
+    pub const fn en_passant_capturable(self) -> bool {
+        self.en_passant_capturable
+    }
+
+    #[inline]
+    pub fn set_en_passant_capturable(&mut self) {
+        self.en_passant_capturable = true;
+    }
+
+    #[inline]
+    pub const fn halfmove_clock(self) -> u8 {
+        self.halfmove_clock
+    }
+
+    #[inline]
+    pub fn set_halfmove_clock(&mut self, value: u8) {
+        self.halfmove_clock = value;
+    }
+
+    #[inline]
+    pub const fn plies_from_null(self) -> u8 {
+        self.plies_from_null
+    }
+
+    #[inline]
+    pub fn set_plies_from_null(&mut self, value: u8) {
+        self.plies_from_null = value;
+    }
+
+    /// The 4 castling bits, packed into the lower half of the value
+    #[inline]
+    pub const fn castling_rights(self) -> u8 {
+        self.bitfield >> 4
     }
 
     #[inline]
@@ -80,11 +124,15 @@ impl GameState {
 }
 
 impl Default for GameState {
-    /// Default state is no en passant square, and no castling rights
+    /// Default state is no en passant square, no castling rights, a cleared halfmove clock
+    /// and no null move made
     fn default() -> Self {
         Self {
             // 8 Represents no en passant square
             bitfield: 8,
+            halfmove_clock: 0,
+            plies_from_null: u8::MAX,
+            en_passant_capturable: false,
         }
     }
 }
@@ -2,15 +2,22 @@
 
 mod autoplay;
 mod benchmark;
+mod bitboard;
 mod chess_game;
+mod game_result;
 mod gamestate;
+mod move_generation;
 mod move_struct;
+mod options;
 mod performance_test;
+mod pgn;
 mod piece;
 mod position;
 mod scores;
 mod search;
+mod transposition_table;
 mod uci;
+mod zobrist;
 
 use arrayvec::ArrayVec;
 use chess_game::ChessGame;
@@ -51,7 +58,7 @@ fn main() {
             }
 
             let mut moves = ArrayVec::new();
-            game.get_moves(&mut moves, true);
+            game.get_moves(&mut moves);
 
             moves.sort_by_cached_key(|_move| _move.uci_notation());
 
@@ -63,12 +70,32 @@ fn main() {
                 sum += count;
                 println!("{}: {}", _move.uci_notation(), count);
             }
-            println!("");
+            println!();
             println!("{}", sum);
+        } else if arg == "pgn" {
+            // Replay the games of a PGN file, printing their final positions
+            let path = args.next().unwrap_or_default();
+            let games = std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|pgn| pgn::read_pgn(&pgn));
+            match games {
+                Ok(games) => {
+                    for pgn_game in games {
+                        println!(
+                            "{} - {} {}",
+                            pgn_game.tag("White").unwrap_or("?"),
+                            pgn_game.tag("Black").unwrap_or("?"),
+                            pgn_game.result
+                        );
+                        println!("{}", pgn_game.game.to_fen());
+                    }
+                }
+                Err(err) => eprintln!("{:?}", err.context("invalid PGN file")),
+            }
         } else if arg == "auto" {
-            // Auto play in terminal
+            // Auto play in terminal, optionally saving the game to a PGN file
             let millis = get_parameter(&mut args, 1000);
-            autoplay::autoplay(millis);
+            autoplay::autoplay(millis, args.next());
         }
     } else {
         // Enter UCI mode
@@ -0,0 +1,602 @@
+// Source: https://www.chessprogramming.org/Move_Generation#Legal
+
+use arrayvec::ArrayVec;
+
+use crate::bitboard::{self, Bitboard, RANK_1, RANK_8};
+use crate::chess_game::{ChessGame, Players};
+use crate::move_struct::Move;
+use crate::piece::{Piece, PieceTypes};
+use crate::position::Position;
+
+const FILE_A: Bitboard = 0x0101010101010101;
+const FILE_H: Bitboard = FILE_A << 7;
+const RANK_3: Bitboard = RANK_1 << 16;
+const RANK_6: Bitboard = RANK_1 << 40;
+
+/// Shifts the bitboard towards higher indexes for a positive offset, lower for a negative one
+#[inline]
+fn shift(bitboard: Bitboard, offset: i8) -> Bitboard {
+    if offset > 0 {
+        bitboard << offset
+    } else {
+        bitboard >> -offset
+    }
+}
+
+/// Subsets of the legal moves which can be generated separately
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum MoveKinds {
+    All,
+    /// Captures (including en passant) and promotions
+    Captures,
+    /// Every other move, including castling
+    Quiets,
+}
+
+/// Everything about the current position that restricts which moves are legal,
+/// computed once before generating the moves
+struct Restrictions {
+    king: Position,
+    /// Enemy pieces giving check to our king
+    checkers: Bitboard,
+    /// Squares our pieces (other than the king) must move to in order to stop a check,
+    /// i.e. capturing the checker or blocking it, all squares when not in check
+    check_mask: Bitboard,
+    /// Our pieces which can't leave the line between the king and an enemy slider
+    pinned: Bitboard,
+}
+
+impl ChessGame {
+    /// Generates all the legal moves in the position
+    ///
+    /// `moves` will be cleared by this function to be sure it has room for all moves
+    pub fn get_moves(&self, moves: &mut ArrayVec<Move, 256>) {
+        self.get_moves_of_kinds(moves, MoveKinds::All);
+    }
+
+    /// Generates only the legal captures, en passant and promotions (including quiet ones)
+    ///
+    /// `moves` will be cleared by this function to be sure it has room for all moves
+    pub fn get_captures(&self, moves: &mut ArrayVec<Move, 256>) {
+        self.get_moves_of_kinds(moves, MoveKinds::Captures);
+    }
+
+    /// Generates the legal moves not returned by get_captures, castling included
+    ///
+    /// `moves` will be cleared by this function to be sure it has room for all moves
+    pub fn get_quiet_moves(&self, moves: &mut ArrayVec<Move, 256>) {
+        self.get_moves_of_kinds(moves, MoveKinds::Quiets);
+    }
+
+    /// Generates the legal quiet moves which give check, either with the moved piece
+    /// or by uncovering an attack of another piece, without playing them
+    ///
+    /// `moves` will be cleared by this function to be sure it has room for all moves
+    pub fn get_quiet_checks(&self, moves: &mut ArrayVec<Move, 256>) {
+        moves.clear();
+
+        let player = self.current_player;
+        let king = self.get_king_position(player.the_other());
+        let occupied = self.occupied_bitboard();
+        // Squares from which each type of piece attacks the enemy king
+        let check_squares = |piece_type| match piece_type {
+            PieceTypes::Queen => bitboard::queen_attacks(king, occupied),
+            PieceTypes::Rook => bitboard::rook_attacks(king, occupied),
+            PieceTypes::Bishop => bitboard::bishop_attacks(king, occupied),
+            PieceTypes::Knight => bitboard::knight_attacks(king),
+            PieceTypes::Pawn => bitboard::pawn_attacks(king, player.the_other()),
+            PieceTypes::King => 0,
+        };
+        // Our pieces hiding an attack on the enemy king, like pinned pieces do for ours
+        let discovering = self.blockers(king, player) & self.player_bitboard(player);
+
+        self.get_legal_moves(
+            |_move| {
+                let gives_check = match _move {
+                    Move::Normal {
+                        piece, start, end, ..
+                    } => {
+                        check_squares(piece.piece_type) & bitboard::square(end) != 0
+                            || (discovering & bitboard::square(start) != 0
+                                && bitboard::line(king, start) & bitboard::square(end) == 0)
+                    }
+                    Move::CastlingShort { .. } | Move::CastlingLong { .. } => {
+                        self.castling_gives_check(_move, king)
+                    }
+                    // Captures aren't generated
+                    Move::Promotion { .. } | Move::EnPassant { .. } => false,
+                };
+                if gives_check {
+                    // SAFETY: There are never more than 256 legal moves
+                    unsafe {
+                        moves.push_unchecked(_move);
+                    }
+                }
+            },
+            MoveKinds::Quiets,
+            !0,
+        );
+    }
+
+    /// Whether castling gives check with the rook, or by uncovering another slider
+    fn castling_gives_check(&self, _move: Move, king: Position) -> bool {
+        let player = self.current_player;
+        let row = match player {
+            Players::White => 0,
+            Players::Black => 7,
+        };
+        let (king_end, rook_start, rook_end) = match _move {
+            Move::CastlingShort { .. } => (6, 7, 5),
+            _ => (2, 0, 3),
+        };
+        let square = |col| bitboard::square(Position::new_assert(row, col));
+
+        let occupied = (self.occupied_bitboard() ^ square(4) ^ square(rook_start))
+            | square(king_end)
+            | square(rook_end);
+        let queens = self.piece_bitboard(PieceTypes::Queen, player);
+        let rooks =
+            (self.piece_bitboard(PieceTypes::Rook, player) ^ square(rook_start)) | square(rook_end);
+        let bishops = self.piece_bitboard(PieceTypes::Bishop, player);
+
+        (bitboard::rook_attacks(king, occupied) & (rooks | queens))
+            | (bitboard::bishop_attacks(king, occupied) & (bishops | queens))
+            != 0
+    }
+
+    /// Generates the legal moves while in check
+    ///
+    /// Since the generator only emits moves which resolve the check,
+    /// these are all the legal moves, captures and quiets alike
+    ///
+    /// `moves` will be cleared by this function to be sure it has room for all moves
+    pub fn get_evasions(&self, moves: &mut ArrayVec<Move, 256>) {
+        debug_assert!(self.is_in_check());
+        self.get_moves_of_kinds(moves, MoveKinds::All);
+    }
+
+    fn get_moves_of_kinds(&self, moves: &mut ArrayVec<Move, 256>, kinds: MoveKinds) {
+        moves.clear();
+
+        self.get_legal_moves(
+            |_move| {
+                // SAFETY: The number of possible moves on the board at any given time
+                // will never exceed the arrays capacity (256)
+                unsafe {
+                    moves.push_unchecked(_move);
+                }
+            },
+            kinds,
+            !0,
+        );
+    }
+
+    /// Returns if the move can be played in the current position
+    ///
+    /// Useful for moves which don't come from the move generator, e.g. stored ones,
+    /// only the moves of the piece on the starting square are generated
+    pub fn is_legal(&self, _move: Move) -> bool {
+        let player = self.current_player;
+        let (owner, start) = match _move {
+            Move::Normal { piece, start, .. } => (piece.owner, start),
+            Move::Promotion { owner, start, .. } => (owner, start),
+            Move::CastlingShort { owner } | Move::CastlingLong { owner } => {
+                (owner, self.get_king_position(owner))
+            }
+            Move::EnPassant {
+                owner, start_col, ..
+            } => {
+                let row = match owner {
+                    Players::White => 4,
+                    Players::Black => 3,
+                };
+                (owner, Position::new_assert(row, start_col))
+            }
+        };
+
+        if owner != player {
+            return false;
+        }
+
+        let mut is_legal = false;
+        self.get_legal_moves(
+            |legal_move| is_legal |= legal_move == _move,
+            MoveKinds::All,
+            bitboard::square(start),
+        );
+        is_legal
+    }
+
+    pub fn is_in_check(&self) -> bool {
+        let player = self.current_player;
+        self.is_targeted(self.get_king_position(player), player)
+    }
+
+    /// All pieces, of both players, attacking the position given the occupied squares
+    fn attackers(&self, position: Position, occupied: Bitboard) -> Bitboard {
+        let pieces = |piece_type| {
+            self.piece_bitboard(piece_type, Players::White)
+                | self.piece_bitboard(piece_type, Players::Black)
+        };
+        let queens = pieces(PieceTypes::Queen);
+
+        (bitboard::knight_attacks(position) & pieces(PieceTypes::Knight))
+            | (bitboard::king_attacks(position) & pieces(PieceTypes::King))
+            | (bitboard::pawn_attacks(position, Players::White)
+                & self.piece_bitboard(PieceTypes::Pawn, Players::Black))
+            | (bitboard::pawn_attacks(position, Players::Black)
+                & self.piece_bitboard(PieceTypes::Pawn, Players::White))
+            | (bitboard::bishop_attacks(position, occupied) & (pieces(PieceTypes::Bishop) | queens))
+            | (bitboard::rook_attacks(position, occupied) & (pieces(PieceTypes::Rook) | queens))
+    }
+
+    /// Returns if player's position is targeted by enemy pieces
+    pub fn is_targeted(&self, position: Position, player: Players) -> bool {
+        self.attackers(position, self.occupied_bitboard())
+            & self.player_bitboard(player.the_other())
+            != 0
+    }
+
+    /// Pieces of either player which are the only ones between the king and a slider
+    /// of the attacker, so that moving them off the line uncovers an attack on the king
+    fn blockers(&self, king: Position, attacker: Players) -> Bitboard {
+        let occupied = self.occupied_bitboard();
+        let queens = self.piece_bitboard(PieceTypes::Queen, attacker);
+
+        // Sliders that would attack the king if there were no pieces in between
+        let snipers = (bitboard::rook_attacks(king, 0)
+            & (self.piece_bitboard(PieceTypes::Rook, attacker) | queens))
+            | (bitboard::bishop_attacks(king, 0)
+                & (self.piece_bitboard(PieceTypes::Bishop, attacker) | queens));
+
+        let mut blockers = 0;
+        for sniper in bitboard::squares(snipers) {
+            let between = bitboard::between(king, sniper) & occupied;
+            if between.count_ones() == 1 {
+                blockers |= between;
+            }
+        }
+        blockers
+    }
+
+    fn get_restrictions(&self) -> Restrictions {
+        let player = self.current_player;
+        let enemy = player.the_other();
+        let king = self.get_king_position(player);
+        let occupied = self.occupied_bitboard();
+
+        let checkers = self.attackers(king, occupied) & self.player_bitboard(enemy);
+        let check_mask = match checkers.count_ones() {
+            0 => !0,
+            1 => {
+                let checker = Position::from_usize(checkers.trailing_zeros() as usize);
+                bitboard::between(king, checker) | checkers
+            }
+            // In a double check only the king can move
+            _ => 0,
+        };
+
+        Restrictions {
+            king,
+            checkers,
+            check_mask,
+            pinned: self.blockers(king, enemy) & self.player_bitboard(player),
+        }
+    }
+
+    /// Generates the legal moves of the given kinds, of the pieces placed on the `from` squares
+    fn get_legal_moves(&self, mut push: impl FnMut(Move), kinds: MoveKinds, from: Bitboard) {
+        let restrictions = self.get_restrictions();
+        let player = self.current_player;
+
+        let targets = match kinds {
+            MoveKinds::All => !self.player_bitboard(player),
+            MoveKinds::Captures => self.player_bitboard(player.the_other()),
+            MoveKinds::Quiets => !self.occupied_bitboard(),
+        };
+
+        if from & bitboard::square(restrictions.king) != 0 {
+            self.get_king_moves(&mut push, &restrictions, targets, kinds);
+        }
+
+        // In a double check only the king can move
+        if restrictions.checkers.count_ones() > 1 {
+            return;
+        }
+
+        let targets = targets & restrictions.check_mask;
+        let occupied = self.occupied_bitboard();
+
+        self.get_pawn_moves(&mut push, &restrictions, kinds, from);
+
+        for piece_type in [
+            PieceTypes::Knight,
+            PieceTypes::Bishop,
+            PieceTypes::Rook,
+            PieceTypes::Queen,
+        ] {
+            let piece = Piece {
+                piece_type,
+                owner: player,
+            };
+            for start in bitboard::squares(self.piece_bitboard(piece_type, player) & from) {
+                let mut attacks = match piece_type {
+                    PieceTypes::Knight => bitboard::knight_attacks(start),
+                    PieceTypes::Bishop => bitboard::bishop_attacks(start, occupied),
+                    PieceTypes::Rook => bitboard::rook_attacks(start, occupied),
+                    _ => bitboard::queen_attacks(start, occupied),
+                } & targets;
+
+                // Pinned pieces can only move along the line of the pin
+                if restrictions.pinned & bitboard::square(start) != 0 {
+                    attacks &= bitboard::line(restrictions.king, start);
+                }
+
+                for end in bitboard::squares(attacks) {
+                    push(Move::Normal {
+                        piece,
+                        start,
+                        end,
+                        captured_piece: self.get_position(end),
+                    });
+                }
+            }
+        }
+    }
+
+    fn get_pawn_moves(
+        &self,
+        mut push: impl FnMut(Move),
+        restrictions: &Restrictions,
+        kinds: MoveKinds,
+        from: Bitboard,
+    ) {
+        let player = self.current_player;
+        let pawns = self.piece_bitboard(PieceTypes::Pawn, player) & from;
+        let enemies = self.player_bitboard(player.the_other());
+        let empty = !self.occupied_bitboard();
+
+        // Offset of the index when moving forward
+        let (forward, double_push_rank, last_rank) = match player {
+            Players::White => (8, RANK_3, RANK_8),
+            Players::Black => (-8, RANK_6, RANK_1),
+        };
+
+        let pawn = Piece {
+            piece_type: PieceTypes::Pawn,
+            owner: player,
+        };
+        let mut push_pawn_moves = |targets: Bitboard, offset: i8| {
+            for end in bitboard::squares(targets & restrictions.check_mask) {
+                let start = Position::from_usize((end.as_usize() as i8 - offset) as usize);
+                if restrictions.pinned & bitboard::square(start) != 0
+                    && bitboard::line(restrictions.king, start) & bitboard::square(end) == 0
+                {
+                    continue;
+                }
+
+                let captured_piece = self.get_position(end);
+                if bitboard::square(end) & last_rank != 0 {
+                    for new_piece in [
+                        PieceTypes::Queen,
+                        PieceTypes::Rook,
+                        PieceTypes::Bishop,
+                        PieceTypes::Knight,
+                    ] {
+                        push(Move::Promotion {
+                            owner: player,
+                            start,
+                            end,
+                            captured_piece,
+                            new_piece,
+                        });
+                    }
+                } else {
+                    push(Move::Normal {
+                        piece: pawn,
+                        start,
+                        end,
+                        captured_piece,
+                    });
+                }
+            }
+        };
+
+        // Promotions count as captures, even when moving forward
+        let push_targets = match kinds {
+            MoveKinds::All => !0,
+            MoveKinds::Captures => last_rank,
+            MoveKinds::Quiets => !last_rank,
+        };
+
+        let single_pushes = shift(pawns, forward) & empty;
+        let double_pushes = shift(single_pushes & double_push_rank, forward) & empty;
+        push_pawn_moves(single_pushes & push_targets, forward);
+        push_pawn_moves(double_pushes & push_targets, 2 * forward);
+
+        if kinds == MoveKinds::Quiets {
+            return;
+        }
+
+        // Captures towards the h file, then towards the a file
+        push_pawn_moves(shift(pawns & !FILE_H, forward + 1) & enemies, forward + 1);
+        push_pawn_moves(shift(pawns & !FILE_A, forward - 1) & enemies, forward - 1);
+
+        let en_passant = self.state().en_passant();
+        if en_passant < 8 {
+            let (end_row, captured_row) = match player {
+                Players::White => (5, 4),
+                Players::Black => (2, 3),
+            };
+            let end = Position::new_assert(end_row, en_passant);
+            let captured = Position::new_assert(captured_row, en_passant);
+
+            // Our pawns which could capture on the square are the ones
+            // an enemy pawn placed on that square would attack
+            for start in bitboard::squares(bitboard::pawn_attacks(end, player.the_other()) & pawns)
+            {
+                // En passant removes two pieces from the same rank, and can uncover attacks
+                // in ways pins don't describe, so the resulting position is verified directly
+                let occupied = (self.occupied_bitboard()
+                    ^ bitboard::square(start)
+                    ^ bitboard::square(captured))
+                    | bitboard::square(end);
+                let attackers = self.attackers(restrictions.king, occupied)
+                    & enemies
+                    & !bitboard::square(captured);
+                if attackers != 0 {
+                    continue;
+                }
+
+                push(Move::EnPassant {
+                    owner: player,
+                    start_col: start.col(),
+                    end_col: en_passant,
+                });
+            }
+        }
+    }
+
+    fn get_king_moves(
+        &self,
+        mut push: impl FnMut(Move),
+        restrictions: &Restrictions,
+        targets: Bitboard,
+        kinds: MoveKinds,
+    ) {
+        let player = self.current_player;
+        let enemies = self.player_bitboard(player.the_other());
+        let start = restrictions.king;
+        let king = Piece {
+            piece_type: PieceTypes::King,
+            owner: player,
+        };
+
+        // The king is removed so that it doesn't hide the squares behind it from sliders
+        let occupied = self.occupied_bitboard() ^ bitboard::square(start);
+        let is_safe = |position| self.attackers(position, occupied) & enemies == 0;
+
+        for end in bitboard::squares(bitboard::king_attacks(start) & targets) {
+            if is_safe(end) {
+                push(Move::Normal {
+                    piece: king,
+                    start,
+                    end,
+                    captured_piece: self.get_position(end),
+                });
+            }
+        }
+
+        if restrictions.checkers != 0 || kinds == MoveKinds::Captures {
+            return;
+        }
+
+        let state = self.state();
+        let (king_side_castling, queen_side_castling) = match player {
+            Players::White => (state.white_king_castling(), state.white_queen_castling()),
+            Players::Black => (state.black_king_castling(), state.black_queen_castling()),
+        };
+        if !king_side_castling && !queen_side_castling {
+            return;
+        }
+
+        let row = match player {
+            Players::White => 0,
+            Players::Black => 7,
+        };
+        let is_free = |cols: &[i8]| {
+            cols.iter()
+                .all(|&col| occupied & bitboard::square(Position::new_assert(row, col)) == 0)
+        };
+        let are_safe = |cols: &[i8]| {
+            cols.iter()
+                .all(|&col| is_safe(Position::new_assert(row, col)))
+        };
+
+        if king_side_castling && is_free(&[5, 6]) && are_safe(&[5, 6]) {
+            push(Move::CastlingShort { owner: player });
+        }
+        if queen_side_castling && is_free(&[1, 2, 3]) && are_safe(&[3, 2]) {
+            push(Move::CastlingLong { owner: player });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Walks the game tree verifying that captures and quiet moves split all the moves
+    fn verify_kinds(game: &mut ChessGame, depth: u8) {
+        let (mut moves, mut captures, mut quiets) =
+            (ArrayVec::new(), ArrayVec::new(), ArrayVec::new());
+        game.get_moves(&mut moves);
+        game.get_captures(&mut captures);
+        game.get_quiet_moves(&mut quiets);
+
+        assert_eq!(moves.len(), captures.len() + quiets.len());
+        assert!(captures.iter().all(|_move| !_move.is_quiet()));
+        assert!(quiets.iter().all(|_move| _move.is_quiet()));
+        assert!(moves.iter().all(|_move| game.is_legal(*_move)));
+
+        let mut quiet_checks = ArrayVec::new();
+        game.get_quiet_checks(&mut quiet_checks);
+        quiets.retain(|_move| {
+            game.push(*_move);
+            let gives_check = game.is_in_check();
+            game.pop(*_move);
+            gives_check
+        });
+        assert_eq!(quiet_checks, quiets);
+
+        if depth == 0 {
+            return;
+        }
+        for _move in moves {
+            game.push(_move);
+            verify_kinds(game, depth - 1);
+            game.pop(_move);
+        }
+    }
+
+    #[test]
+    fn captures_and_quiets_kiwipete() {
+        let mut game =
+            ChessGame::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -")
+                .unwrap();
+        verify_kinds(&mut game, 3);
+    }
+
+    #[test]
+    fn captures_and_quiets_position_4() {
+        let mut game =
+            ChessGame::new("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0")
+                .unwrap();
+        verify_kinds(&mut game, 3);
+    }
+
+    #[test]
+    fn quiet_checks() {
+        for fen in [
+            // Discovered checks by the king and by other pieces
+            "8/8/1P2K3/8/2n5/1q6/8/5k2 b - - 0 1",
+            "4k3/8/8/8/8/8/1B2N3/R3K2R w KQ - 0 1",
+            // Castling gives check
+            "5k2/8/8/8/8/8/8/4K2R w K - 0 1",
+            "3k4/8/8/8/8/8/8/R3K3 w Q - 0 1",
+        ] {
+            let mut game = ChessGame::new(fen).unwrap();
+            verify_kinds(&mut game, 3);
+        }
+    }
+
+    #[test]
+    fn illegal_moves_are_rejected() {
+        let game = ChessGame::default();
+        let black_move = Move::from_uci_notation("e7e5", &game).unwrap();
+        let blocked_move = Move::from_uci_notation("f1c4", &game).unwrap();
+        assert!(!game.is_legal(black_move));
+        assert!(!game.is_legal(blocked_move));
+        assert!(game.is_legal(Move::from_uci_notation("e2e4", &game).unwrap()));
+    }
+}
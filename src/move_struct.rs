@@ -1,4 +1,5 @@
-use std::str::FromStr;
+use anyhow::{bail, Context};
+use arrayvec::ArrayVec;
 
 use crate::chess_game::{ChessGame, Players};
 use crate::piece::{Piece, PieceTypes};
@@ -48,6 +49,25 @@ impl Move {
         }
     }
 
+    /// Moves which neither capture nor promote
+    pub fn is_quiet(&self) -> bool {
+        match self {
+            Self::Normal { captured_piece, .. } => captured_piece.is_none(),
+            Self::CastlingShort { .. } | Self::CastlingLong { .. } => true,
+            Self::Promotion { .. } | Self::EnPassant { .. } => false,
+        }
+    }
+
+    pub fn is_capture(&self) -> bool {
+        match self {
+            Self::Normal { captured_piece, .. } | Self::Promotion { captured_piece, .. } => {
+                captured_piece.is_some()
+            }
+            Self::EnPassant { .. } => true,
+            Self::CastlingShort { .. } | Self::CastlingLong { .. } => false,
+        }
+    }
+
     pub fn uci_notation(&self) -> String {
         let mut s = String::new();
         match self {
@@ -113,64 +133,277 @@ impl Move {
         s
     }
 
-    pub fn pgn_notation(&self) -> String {
-        match self {
+    /// Standard Algebraic Notation of the move, played in the position of the game
+    ///
+    /// The move is pushed and popped back to find out whether it gives check or mate
+    pub fn san_notation(&self, game: &mut ChessGame) -> String {
+        let mut s = String::new();
+        match *self {
+            Self::CastlingShort { .. } => s.push_str("O-O"),
+            Self::CastlingLong { .. } => s.push_str("O-O-O"),
             Self::Normal {
                 piece,
                 start,
                 end,
                 captured_piece,
-            } => {
-                let mut s = String::new();
+            } if piece.piece_type != PieceTypes::Pawn => {
                 s.push_str(piece.as_char_ascii());
-                s.push(((start.col()) as u8 + b'a') as char);
+                self.push_disambiguation(&mut s, game, piece.piece_type, start, end);
                 if captured_piece.is_some() {
                     s.push('x');
                 }
-                s.push(((end.col()) as u8 + b'a') as char);
-                s.push_str((end.row() + 1).to_string().as_str());
-                s
+                push_square(&mut s, end);
+            }
+            Self::Normal {
+                start,
+                end,
+                captured_piece,
+                ..
+            }
+            | Self::Promotion {
+                start,
+                end,
+                captured_piece,
+                ..
+            } => {
+                if captured_piece.is_some() {
+                    s.push((start.col() as u8 + b'a') as char);
+                    s.push('x');
+                }
+                push_square(&mut s, end);
+                if let Self::Promotion { new_piece, .. } = self {
+                    s.push('=');
+                    s.push_str(
+                        Piece {
+                            piece_type: *new_piece,
+                            owner: game.current_player,
+                        }
+                        .as_char_ascii(),
+                    );
+                }
             }
-            Self::CastlingShort { .. } => String::from_str("O-O").unwrap(),
-            Self::CastlingLong { .. } => String::from_str("O-O-O").unwrap(),
             Self::EnPassant {
+                owner,
                 start_col,
                 end_col,
-                owner,
             } => {
-                let mut s = String::new();
-                s.push((*start_col as u8 + b'a') as char);
+                s.push((start_col as u8 + b'a') as char);
                 s.push('x');
-                s.push((*end_col as u8 + b'a') as char);
-                match owner {
-                    Players::White => s.push('6'),
-                    Players::Black => s.push('3'),
+                s.push((end_col as u8 + b'a') as char);
+                s.push(match owner {
+                    Players::White => '6',
+                    Players::Black => '3',
+                });
+            }
+        }
+
+        game.push(*self);
+        if game.is_in_check() {
+            let mut evasions = ArrayVec::new();
+            game.get_evasions(&mut evasions);
+            s.push(if evasions.is_empty() { '#' } else { '+' });
+        }
+        game.pop(*self);
+
+        s
+    }
+
+    /// Adds the file, the rank, or both, of the starting square, if needed to tell
+    /// the move apart from other moves of pieces of the same type to the same square
+    fn push_disambiguation(
+        &self,
+        s: &mut String,
+        game: &ChessGame,
+        piece_type: PieceTypes,
+        start: Position,
+        end: Position,
+    ) {
+        let mut moves = ArrayVec::new();
+        game.get_moves(&mut moves);
+
+        let mut ambiguous = false;
+        let mut same_col = false;
+        let mut same_row = false;
+        for _move in moves {
+            if let Self::Normal {
+                piece,
+                start: other_start,
+                end: other_end,
+                ..
+            } = _move
+            {
+                if piece.piece_type == piece_type && other_end == end && other_start != start {
+                    ambiguous = true;
+                    same_col |= other_start.col() == start.col();
+                    same_row |= other_start.row() == start.row();
+                }
+            }
+        }
+
+        if ambiguous && (!same_col || same_row) {
+            s.push((start.col() as u8 + b'a') as char);
+        }
+        if same_col {
+            s.push((start.row() as u8 + b'1') as char);
+        }
+    }
+
+    /// Start and end squares of the moved piece, which is the king for castling
+    pub fn start_end(&self) -> (Position, Position) {
+        match *self {
+            Self::CastlingShort { owner } | Self::CastlingLong { owner } => {
+                let row = match owner {
+                    Players::White => 0,
+                    Players::Black => 7,
                 };
-                s
+                let col = if matches!(self, Self::CastlingShort { .. }) {
+                    6
+                } else {
+                    2
+                };
+                (Position::new_assert(row, 4), Position::new_assert(row, col))
+            }
+            _ => {
+                // Only castling moves have no squares
+                let (_, start, end, _) = self.squares().unwrap();
+                (start, end)
             }
+        }
+    }
+
+    /// The moved piece type, start, end and promoted piece type of non castling moves
+    fn squares(&self) -> Option<(PieceTypes, Position, Position, Option<PieceTypes>)> {
+        match *self {
+            Self::Normal {
+                piece, start, end, ..
+            } => Some((piece.piece_type, start, end, None)),
             Self::Promotion {
-                end,
-                captured_piece,
                 new_piece,
+                start,
+                end,
                 ..
+            } => Some((PieceTypes::Pawn, start, end, Some(new_piece))),
+            Self::EnPassant {
+                owner,
+                start_col,
+                end_col,
             } => {
-                let mut s = String::new();
-                if captured_piece.is_some() {
-                    s.push('x');
+                let (start_row, end_row) = match owner {
+                    Players::White => (4, 5),
+                    Players::Black => (3, 2),
+                };
+                Some((
+                    PieceTypes::Pawn,
+                    Position::new_assert(start_row, start_col),
+                    Position::new_assert(end_row, end_col),
+                    None,
+                ))
+            }
+            Self::CastlingShort { .. } | Self::CastlingLong { .. } => None,
+        }
+    }
+
+    /// Parses a move in Standard Algebraic Notation, played in the position of the game
+    ///
+    /// Check, mate and annotation suffixes are accepted, but not verified
+    pub fn from_san(s: &str, game: &ChessGame) -> anyhow::Result<Self> {
+        let san = s.trim_end_matches(['+', '#', '!', '?']);
+        if !san.is_ascii() {
+            bail!("Invalid SAN move {}", s);
+        }
+
+        let mut moves = ArrayVec::new();
+        game.get_moves(&mut moves);
+
+        if let Some(long) = match san {
+            "O-O" | "0-0" => Some(false),
+            "O-O-O" | "0-0-0" => Some(true),
+            _ => None,
+        } {
+            return moves
+                .into_iter()
+                .find(|_move| match _move {
+                    Self::CastlingShort { .. } => !long,
+                    Self::CastlingLong { .. } => long,
+                    _ => false,
+                })
+                .with_context(|| format!("Illegal move {}", s));
+        }
+
+        let mut san = san.as_bytes();
+        let piece_type = match san.first() {
+            Some(b'K') => PieceTypes::King,
+            Some(b'Q') => PieceTypes::Queen,
+            Some(b'R') => PieceTypes::Rook,
+            Some(b'B') => PieceTypes::Bishop,
+            Some(b'N') => PieceTypes::Knight,
+            _ => PieceTypes::Pawn,
+        };
+        if piece_type != PieceTypes::Pawn {
+            san = &san[1..];
+        }
+
+        let mut promotion = None;
+        if piece_type == PieceTypes::Pawn {
+            if let Some((&last, rest)) = san.split_last() {
+                promotion = match last {
+                    b'Q' => Some(PieceTypes::Queen),
+                    b'R' => Some(PieceTypes::Rook),
+                    b'B' => Some(PieceTypes::Bishop),
+                    b'N' => Some(PieceTypes::Knight),
+                    _ => None,
+                };
+                if promotion.is_some() {
+                    san = rest.strip_suffix(b"=").unwrap_or(rest);
                 }
-                s.push(((end.col()) as u8 + b'a') as char);
-                s.push_str((end.row() + 1).to_string().as_str());
-                s.push('=');
-                s.push(match new_piece {
-                    PieceTypes::Queen => 'Q',
-                    PieceTypes::Rook => 'R',
-                    PieceTypes::Bishop => 'K',
-                    PieceTypes::Knight => 'B',
-                    _ => unreachable!(),
-                });
-                s
             }
         }
+
+        let [rest @ .., end_col, end_row] = san else {
+            bail!("Invalid SAN move {}", s);
+        };
+        let end = Position::new(
+            end_row.wrapping_sub(b'1') as i8,
+            end_col.wrapping_sub(b'a') as i8,
+        )
+        .with_context(|| format!("Invalid destination square in {}", s))?;
+
+        let (mut start_col, mut start_row, mut is_capture) = (None, None, false);
+        for &character in rest {
+            match character {
+                b'a'..=b'h' => start_col = Some((character - b'a') as i8),
+                b'1'..=b'8' => start_row = Some((character - b'1') as i8),
+                b'x' | b':' => is_capture = true,
+                _ => bail!("Invalid SAN move {}", s),
+            }
+        }
+
+        let mut candidates = moves.into_iter().filter(|_move| {
+            _move
+                .squares()
+                .is_some_and(|(move_piece, start, move_end, move_promotion)| {
+                    move_piece == piece_type
+                        && move_end == end
+                        && move_promotion == promotion
+                        && start_col.is_none_or(|col| col == start.col())
+                        && start_row.is_none_or(|row| row == start.row())
+                        && (!is_capture || _move.is_capture())
+                })
+        });
+
+        let Some(_move) = candidates.next() else {
+            bail!("Illegal move {}", s);
+        };
+        if let Some(other) = candidates.next() {
+            bail!(
+                "Ambiguous move {}, it could be {} or {}",
+                s,
+                _move.uci_notation(),
+                other.uci_notation()
+            );
+        }
+
+        Ok(_move)
     }
 
     pub fn from_uci_notation(s: &str, game: &ChessGame) -> Option<Self> {
@@ -250,6 +483,138 @@ impl Move {
     }
 }
 
+fn push_square(s: &mut String, position: Position) {
+    s.push((position.col() as u8 + b'a') as char);
+    s.push((position.row() as u8 + b'1') as char);
+}
+
+/// Bit layout of a packed move, see `Move::pack`
+const KIND_NORMAL: u32 = 1;
+const KIND_PROMOTION: u32 = 2;
+const KIND_CASTLING_SHORT: u32 = 3;
+const KIND_CASTLING_LONG: u32 = 4;
+const KIND_EN_PASSANT: u32 = 5;
+
+fn piece_type_from_bits(bits: u32) -> Option<PieceTypes> {
+    match bits {
+        0 => Some(PieceTypes::Queen),
+        1 => Some(PieceTypes::Rook),
+        2 => Some(PieceTypes::Bishop),
+        3 => Some(PieceTypes::Knight),
+        4 => Some(PieceTypes::Pawn),
+        5 => Some(PieceTypes::King),
+        _ => None,
+    }
+}
+
+impl Move {
+    /// Packs the move into a non zero u32, so that it can be stored atomically
+    ///
+    /// Layout (from the least significant bit):
+    /// 3 bits kind, 1 bit owner, 6 bits start, 6 bits end,
+    /// 3 bits piece type (moved or promoted to), 3 bits captured piece type + 1 (0 for none)
+    pub fn pack(self) -> u32 {
+        let (kind, owner, start, end, piece_type, captured_piece) = match self {
+            Self::Normal {
+                piece,
+                start,
+                end,
+                captured_piece,
+            } => (
+                KIND_NORMAL,
+                piece.owner,
+                start.as_usize() as u32,
+                end.as_usize() as u32,
+                piece.piece_type as u32,
+                captured_piece,
+            ),
+            Self::Promotion {
+                owner,
+                new_piece,
+                start,
+                end,
+                captured_piece,
+            } => (
+                KIND_PROMOTION,
+                owner,
+                start.as_usize() as u32,
+                end.as_usize() as u32,
+                new_piece as u32,
+                captured_piece,
+            ),
+            Self::CastlingShort { owner } => (KIND_CASTLING_SHORT, owner, 0, 0, 0, None),
+            Self::CastlingLong { owner } => (KIND_CASTLING_LONG, owner, 0, 0, 0, None),
+            Self::EnPassant {
+                owner,
+                start_col,
+                end_col,
+            } => (
+                KIND_EN_PASSANT,
+                owner,
+                start_col as u32,
+                end_col as u32,
+                0,
+                None,
+            ),
+        };
+
+        let owner = match owner {
+            Players::White => 0,
+            Players::Black => 1,
+        };
+        let captured_piece = captured_piece.map_or(0, |piece| piece.piece_type as u32 + 1);
+
+        kind | owner << 3 | start << 4 | end << 10 | piece_type << 16 | captured_piece << 19
+    }
+
+    /// Inverse of `Move::pack`, returns None for values which don't represent a move
+    pub fn unpack(bits: u32) -> Option<Self> {
+        let owner = if bits >> 3 & 1 == 0 {
+            Players::White
+        } else {
+            Players::Black
+        };
+        let start = (bits >> 4 & 0b111111) as i8;
+        let end = (bits >> 10 & 0b111111) as i8;
+        let (start, end) = (
+            Position::new_assert(start / 8, start % 8),
+            Position::new_assert(end / 8, end % 8),
+        );
+        let piece_type = piece_type_from_bits(bits >> 16 & 0b111)?;
+        let captured_piece = match bits >> 19 & 0b111 {
+            0 => None,
+            piece_type => Some(Piece {
+                piece_type: piece_type_from_bits(piece_type - 1)?,
+                owner: owner.the_other(),
+            }),
+        };
+
+        match bits & 0b111 {
+            KIND_NORMAL => Some(Self::Normal {
+                piece: Piece { piece_type, owner },
+                start,
+                end,
+                captured_piece,
+            }),
+            KIND_PROMOTION => Some(Self::Promotion {
+                owner,
+                new_piece: piece_type,
+                start,
+                end,
+                captured_piece,
+            }),
+            KIND_CASTLING_SHORT => Some(Self::CastlingShort { owner }),
+            KIND_CASTLING_LONG => Some(Self::CastlingLong { owner }),
+            KIND_EN_PASSANT => Some(Self::EnPassant {
+                owner,
+                start_col: start.col(),
+                end_col: end.col(),
+            }),
+            _ => None,
+        }
+    }
+}
+
 impl std::fmt::Debug for Move {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -275,3 +640,58 @@ impl std::fmt::Debug for Move {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn san_notation() {
+        for (fen, move_str, san) in [
+            ("4k3/8/8/8/8/5N2/8/1N2K3 w - -", "b1d2", "Nbd2"),
+            ("4k3/8/8/8/8/5N2/8/1N2K3 w - -", "f3g5", "Ng5"),
+            ("4k3/8/8/R7/8/8/8/R3K3 w - -", "a1a3", "R1a3"),
+            ("4k3/8/8/8/8/Q1Q5/8/Q1Q1K3 w - -", "a1b2", "Qa1b2"),
+            ("4k3/8/8/8/8/Q1Q5/8/Q1Q1K3 w - -", "a3a2", "Q3a2"),
+            ("3r3k/4P3/8/8/8/8/8/4K3 w - -", "e7d8q", "exd8=Q+"),
+            ("3r3k/4P3/8/8/8/8/8/4K3 w - -", "e7e8n", "e8=N"),
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - d6", "e5d6", "exd6"),
+            ("4k3/8/8/8/8/8/8/4K2R w K -", "e1g1", "O-O"),
+            (
+                "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2",
+                "d8h4",
+                "Qh4#",
+            ),
+        ] {
+            let mut game = ChessGame::new(fen).unwrap();
+            let _move = Move::from_uci_notation(move_str, &game).unwrap();
+            assert_eq!(_move.san_notation(&mut game), san, "{}", fen);
+            assert!(Move::from_san(san, &game).unwrap() == _move, "{}", san);
+        }
+    }
+
+    #[test]
+    fn from_san() {
+        let game = ChessGame::new("4k3/8/8/8/8/Q1Q5/8/Q1Q1K2R w K -").unwrap();
+        for (san, move_str) in [
+            ("Qa1b2", "a1b2"),
+            ("Qc3b2?!", "c3b2"),
+            ("Q3a2", "a3a2"),
+            ("Qxc3", ""),
+            ("0-0+", "e1g1"),
+            ("Rh8#", "h1h8"),
+        ] {
+            match Move::from_san(san, &game) {
+                Ok(_move) => assert_eq!(_move.uci_notation(), move_str, "{}", san),
+                Err(_) => assert_eq!(move_str, "", "{}", san),
+            }
+        }
+
+        let error = Move::from_san("Qb2", &game).unwrap_err().to_string();
+        assert!(error.starts_with("Ambiguous move"), "{}", error);
+        let error = Move::from_san("O-O-O", &game).unwrap_err().to_string();
+        assert!(error.starts_with("Illegal move"), "{}", error);
+        assert!(Move::from_san("Qz9", &game).is_err());
+        assert!(Move::from_san("", &game).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use std::{fmt, time::Duration};
+
+use anyhow::{bail, Context};
+
+use crate::transposition_table::DEFAULT_SIZE_MB;
+
+#[derive(Clone, Copy, Debug)]
+pub enum OptionType {
+    /// An integer in the range min..=max
+    Spin { default: i64, min: i64, max: i64 },
+    /// Has no value, setting it triggers an action
+    Button,
+}
+
+/// An option the GUI can change with the setoption command
+#[derive(Clone, Copy, Debug)]
+pub struct UciOption {
+    pub name: &'static str,
+    pub option_type: OptionType,
+}
+
+/// All the options the engine supports, advertised in this order after the uci command
+pub static OPTIONS: [UciOption; 6] = [
+    UciOption {
+        name: "Hash",
+        option_type: OptionType::Spin {
+            default: DEFAULT_SIZE_MB as i64,
+            min: 1,
+            max: 4096,
+        },
+    },
+    UciOption {
+        name: "Clear Hash",
+        option_type: OptionType::Button,
+    },
+    UciOption {
+        name: "Threads",
+        option_type: OptionType::Spin {
+            default: 1,
+            min: 1,
+            max: 256,
+        },
+    },
+    UciOption {
+        name: "MultiPV",
+        option_type: OptionType::Spin {
+            default: 1,
+            min: 1,
+            max: 256,
+        },
+    },
+    UciOption {
+        name: "Move Overhead",
+        option_type: OptionType::Spin {
+            default: 100,
+            min: 0,
+            max: 10000,
+        },
+    },
+    // Time spent on a move when the go command gives no limit, if not 0
+    UciOption {
+        name: "Move Time",
+        option_type: OptionType::Spin {
+            default: 0,
+            min: 0,
+            max: 3_600_000,
+        },
+    },
+];
+
+impl fmt::Display for UciOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "option name {} type ", self.name)?;
+        match self.option_type {
+            OptionType::Spin { default, min, max } => {
+                write!(f, "spin default {} min {} max {}", default, min, max)
+            }
+            OptionType::Button => write!(f, "button"),
+        }
+    }
+}
+
+/// The current values of the options, in the units the engine uses them
+#[derive(Clone, Debug)]
+pub struct EngineOptions {
+    pub hash_size_mb: usize,
+    /// Number of threads searching at the same time
+    pub threads: usize,
+    /// Number of best lines reported
+    pub multi_pv: usize,
+    /// Time kept in reserve on every move, for the communication with the GUI
+    pub move_overhead: Duration,
+    /// Time to spend on a move when the go command gives no limit
+    pub move_time: Option<Duration>,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        let mut options = Self {
+            hash_size_mb: 0,
+            threads: 0,
+            multi_pv: 0,
+            move_overhead: Duration::ZERO,
+            move_time: None,
+        };
+        for option in &OPTIONS {
+            if let OptionType::Spin { default, .. } = option.option_type {
+                options.set_spin(option.name, default);
+            }
+        }
+        options
+    }
+}
+
+impl EngineOptions {
+    /// Sets the option with the given name, which is case insensitive, and returns it,
+    /// so the caller can react to it, e.g. by resizing the hash table
+    pub fn set(&mut self, name: &str, value: &str) -> anyhow::Result<&'static UciOption> {
+        let option = OPTIONS
+            .iter()
+            .find(|option| option.name.eq_ignore_ascii_case(name))
+            .with_context(|| format!("Unknown option {}", name))?;
+
+        if let OptionType::Spin { min, max, .. } = option.option_type {
+            let value: i64 = value
+                .parse()
+                .with_context(|| format!("Invalid value {} for {}", value, option.name))?;
+            if !(min..=max).contains(&value) {
+                bail!("{} must be between {} and {}", option.name, min, max);
+            }
+            self.set_spin(option.name, value);
+        }
+
+        Ok(option)
+    }
+
+    /// The value must already be in the range of the option
+    fn set_spin(&mut self, name: &str, value: i64) {
+        match name {
+            "Hash" => self.hash_size_mb = value as usize,
+            "Threads" => self.threads = value as usize,
+            "MultiPV" => self.multi_pv = value as usize,
+            "Move Overhead" => self.move_overhead = Duration::from_millis(value as u64),
+            "Move Time" => {
+                self.move_time =
+                    Some(Duration::from_millis(value as u64)).filter(|time| !time.is_zero())
+            }
+            _ => unreachable!("{} is not a spin option", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_options() {
+        let mut options = EngineOptions::default();
+        assert_eq!(options.hash_size_mb, DEFAULT_SIZE_MB);
+        assert_eq!(options.threads, 1);
+        assert_eq!(options.move_overhead, Duration::from_millis(100));
+        assert_eq!(options.move_time, None);
+
+        assert_eq!(options.set("hash", "64").unwrap().name, "Hash");
+        assert_eq!(options.hash_size_mb, 64);
+        options.set("Move Overhead", "30").unwrap();
+        assert_eq!(options.move_overhead, Duration::from_millis(30));
+        options.set("Move Time", "500").unwrap();
+        assert_eq!(options.move_time, Some(Duration::from_millis(500)));
+        options.set("Clear Hash", "").unwrap();
+
+        assert!(options.set("Threads", "0").is_err());
+        assert!(options.set("MultiPV", "two").is_err());
+        assert!(options.set("Ponder", "true").is_err());
+        assert_eq!(options.threads, 1);
+        assert_eq!(options.multi_pv, 1);
+
+        assert_eq!(
+            OPTIONS[0].to_string(),
+            "option name Hash type spin default 16 min 1 max 4096"
+        );
+        assert_eq!(OPTIONS[1].to_string(), "option name Clear Hash type button");
+    }
+}
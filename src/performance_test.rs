@@ -5,21 +5,16 @@ use super::*;
 /// Performance Test
 pub fn perft(game: &mut ChessGame, depth: u8) -> usize {
     let mut moves = ArrayVec::new();
-    game.get_moves(&mut moves, true);
+    game.get_moves(&mut moves);
 
-    let mut count = 0;
+    // The moves are legal, so at the last ply they don't need to be played
     if depth == 0 {
         return 1;
     } else if depth == 1 {
-        for _move in moves.iter() {
-            let _move = *_move;
-            game.push(_move);
-            count += 1;
-            game.pop(_move);
-        }
         return moves.len();
     }
 
+    let mut count = 0;
     for _move in moves.iter() {
         let _move = *_move;
         game.push(_move);
@@ -31,7 +26,6 @@ pub fn perft(game: &mut ChessGame, depth: u8) -> usize {
 }
 
 #[cfg(test)]
-
 mod tests {
     use super::*;
 
@@ -281,6 +275,54 @@ mod tests {
         assert_eq!(perft(&mut game, 5), 164075551);
     }
 
+    #[test]
+    fn perft6_illegal_en_passant_pin() {
+        let mut game = ChessGame::new("3k4/3p4/8/K1P4r/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(perft(&mut game, 6), 1134888);
+    }
+
+    #[test]
+    fn perft6_illegal_en_passant_diagonal() {
+        let mut game = ChessGame::new("8/8/4k3/8/2p5/8/B2P2K1/8 w - - 0 1").unwrap();
+        assert_eq!(perft(&mut game, 6), 1015133);
+    }
+
+    #[test]
+    fn perft6_en_passant_gives_check() {
+        let mut game = ChessGame::new("8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1").unwrap();
+        assert_eq!(perft(&mut game, 6), 1440467);
+    }
+
+    #[test]
+    fn perft6_castling_gives_check() {
+        let mut game = ChessGame::new("5k2/8/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        assert_eq!(perft(&mut game, 6), 661072);
+    }
+
+    #[test]
+    fn perft4_castling_prevented() {
+        let mut game = ChessGame::new("r3k2r/8/3Q4/8/8/5q2/8/R3K2R b KQkq - 0 1").unwrap();
+        assert_eq!(perft(&mut game, 4), 1720476);
+    }
+
+    #[test]
+    fn perft6_promote_out_of_check() {
+        let mut game = ChessGame::new("2K2r2/4P3/8/8/8/8/8/3k4 w - - 0 1").unwrap();
+        assert_eq!(perft(&mut game, 6), 3821001);
+    }
+
+    #[test]
+    fn perft5_discovered_check() {
+        let mut game = ChessGame::new("8/8/1P2K3/8/2n5/1q6/8/5k2 b - - 0 1").unwrap();
+        assert_eq!(perft(&mut game, 5), 1004658);
+    }
+
+    #[test]
+    fn perft7_stalemate_and_checkmate() {
+        let mut game = ChessGame::new("8/k1P5/8/1K6/8/8/8/8 w - - 0 1").unwrap();
+        assert_eq!(perft(&mut game, 7), 567584);
+    }
+
     #[test]
     fn perft_many_position5() {
         let mut game =
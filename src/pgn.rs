@@ -0,0 +1,443 @@
+// Source: https://www.chessprogramming.org/Portable_Game_Notation
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context};
+
+use crate::chess_game::{ChessGame, Players, MAX_GAME_LENGTH};
+use crate::move_struct::Move;
+use crate::piece::Score;
+use crate::search;
+
+/// Tokens which end the movetext of a game
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+/// Tags every game must have, in the order they have to be written
+const SEVEN_TAG_ROSTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+
+const LINE_WIDTH: usize = 80;
+
+/// A game read from or written to PGN, with the moves of its mainline played
+pub struct PgnGame {
+    /// Tag pairs, in the order they appear
+    pub tags: Vec<(String, String)>,
+    pub game: ChessGame,
+    /// The comment following each move of the game, if any, indexed by ply
+    pub comments: Vec<Option<String>>,
+    /// The result token ending the movetext, "*" if the game is unfinished
+    pub result: String,
+}
+
+impl PgnGame {
+    /// Tags missing from the Seven Tag Roster are written as unknown
+    pub fn new(game: ChessGame, result: &str) -> Self {
+        Self {
+            tags: vec![],
+            comments: vec![None; game.move_stack.len()],
+            game,
+            result: result.to_string(),
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old_value)) => *old_value = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Writes the game in export format: the Seven Tag Roster first, the starting position
+    /// if it isn't the initial one, the other tags, then the movetext wrapped at 80 columns
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        let mut write_tag = |name: &str, value: &str| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, value));
+        };
+
+        for name in SEVEN_TAG_ROSTER {
+            let value = match name {
+                "Result" => &self.result,
+                "Date" => self.tag(name).unwrap_or("????.??.??"),
+                _ => self.tag(name).unwrap_or("?"),
+            };
+            write_tag(name, value);
+        }
+
+        let fen = self.game.starting_position().to_fen();
+        if fen != ChessGame::default().to_fen() {
+            write_tag("SetUp", "1");
+            write_tag("FEN", &fen);
+        }
+
+        for (name, value) in &self.tags {
+            if !SEVEN_TAG_ROSTER.contains(&name.as_str()) && name != "SetUp" && name != "FEN" {
+                write_tag(name, value);
+            }
+        }
+        pgn.push('\n');
+
+        let mut words = movetext(&self.game, &self.comments);
+        words.push(self.result.clone());
+
+        let mut line = String::new();
+        for word in words.iter().flat_map(|word| word.split_whitespace()) {
+            if !line.is_empty() && line.len() + 1 + word.len() > LINE_WIDTH {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        pgn.push_str(&line);
+        pgn.push('\n');
+
+        pgn
+    }
+}
+
+/// Move numbers, moves in SAN and comments of the game, as separate words
+pub fn movetext(game: &ChessGame, comments: &[Option<String>]) -> Vec<String> {
+    // SAN depends on the position, so the game is replayed from its starting position
+    let mut position = game.starting_position();
+    let mut words = vec![];
+
+    for (ply, _move) in game.move_stack.iter().enumerate() {
+        if position.current_player == Players::White {
+            words.push(format!("{}.", position.fullmove_number()));
+        } else if ply == 0 {
+            words.push(format!("{}...", position.fullmove_number()));
+        }
+        words.push(_move.san_notation(&mut position));
+        if let Some(Some(comment)) = comments.get(ply) {
+            // Comments can't be nested
+            words.push(format!("{{{}}}", comment.replace('}', "")));
+        }
+        position.push(*_move);
+    }
+
+    words
+}
+
+/// Comment describing the engine search which found a move, e.g. "+0.35/12 1.250s",
+/// or "+M3/12 1.250s" when it mates in 3
+pub fn engine_comment(score: Score, depth: u8, time: Duration) -> String {
+    let score = match search::moves_to_mate(score) {
+        Some(moves) if moves > 0 => format!("+M{}", moves),
+        Some(moves) => format!("-M{}", -moves),
+        None => format!("{:+.2}", score as f64 / 100.0),
+    };
+    format!("{}/{} {:.3}s", score, depth, time.as_secs_f64())
+}
+
+/// Today's date, in the format of the Date tag
+///
+/// Source: https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() / 86400) as i64;
+
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+/// The position given by the FEN tag, if there is one, otherwise the initial position
+fn starting_position(tags: &[(String, String)]) -> anyhow::Result<ChessGame> {
+    match tags.iter().find(|(name, _)| name == "FEN") {
+        Some((_, fen)) => ChessGame::new(fen).context("Invalid FEN tag"),
+        None => Ok(ChessGame::default()),
+    }
+}
+
+/// Removes move numbers like "12." or "12...", which may be followed by the move
+fn strip_move_number(token: &str) -> &str {
+    match token.rfind('.') {
+        Some(dot)
+            if token[..dot]
+                .bytes()
+                .all(|b| b.is_ascii_digit() || b == b'.') =>
+        {
+            &token[dot + 1..]
+        }
+        _ => token,
+    }
+}
+
+/// Parses a tag pair like `[Event "Casual game"]`, returning it and the text after it
+fn read_tag(text: &str) -> anyhow::Result<((String, String), &str)> {
+    let text = text
+        .strip_prefix('[')
+        .context("Missing tag pair")?
+        .trim_start();
+    let name_end = text
+        .find(|character: char| character.is_whitespace() || character == '"')
+        .context("Unterminated tag pair")?;
+    let name = text[..name_end].to_string();
+
+    let text = text[name_end..].trim_start();
+    let Some(text) = text.strip_prefix('"') else {
+        bail!("Missing value of tag {}", name);
+    };
+
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    let value_end = loop {
+        match chars.next() {
+            Some((_, '\\')) => {
+                if let Some((_, escaped)) = chars.next() {
+                    value.push(escaped);
+                }
+            }
+            Some((index, '"')) => break index,
+            Some((_, character)) => value.push(character),
+            None => bail!("Unterminated value of tag {}", name),
+        }
+    };
+
+    let Some(text) = text[value_end + 1..].trim_start().strip_prefix(']') else {
+        bail!("Unterminated tag pair {}", name);
+    };
+    Ok(((name, value), text))
+}
+
+/// Reads all the games of a PGN file
+///
+/// Only the mainline is played, comments, NAGs and variations are skipped
+pub fn read_pgn(pgn: &str) -> anyhow::Result<Vec<PgnGame>> {
+    let mut games = vec![];
+    let mut tags = vec![];
+    let mut comments = vec![];
+    // Created by the first token of the movetext, once all the tags are known
+    let mut game: Option<ChessGame> = None;
+    let mut variation_depth = 0;
+    let mut text = pgn;
+
+    loop {
+        text = text.trim_start();
+        let Some(character) = text.chars().next() else {
+            break;
+        };
+
+        match character {
+            '[' => {
+                // A tag pair after a movetext without result begins the next game
+                if let Some(game) = game.take() {
+                    games.push(PgnGame {
+                        tags: std::mem::take(&mut tags),
+                        game,
+                        comments: std::mem::take(&mut comments),
+                        result: String::from("*"),
+                    });
+                }
+                let (tag, rest) = read_tag(text)?;
+                tags.push(tag);
+                text = rest;
+            }
+            '{' => {
+                let end = text.find('}').context("Unterminated comment")?;
+                // Comments of the mainline are kept with the move before them
+                if let (Some(game), 0) = (&game, variation_depth) {
+                    if let Some(comment) = comments.get_mut(game.move_stack.len().wrapping_sub(1)) {
+                        *comment = Some(text[1..end].trim().to_string());
+                    }
+                }
+                text = &text[end + 1..];
+            }
+            ';' | '%' => {
+                // Comment or escape, up to the end of the line
+                text = text.find('\n').map_or("", |end| &text[end + 1..]);
+            }
+            '(' => {
+                variation_depth += 1;
+                text = &text[1..];
+            }
+            ')' => {
+                if variation_depth == 0 {
+                    bail!("Unmatched end of variation");
+                }
+                variation_depth -= 1;
+                text = &text[1..];
+            }
+            _ => {
+                let end = text
+                    .find(|character: char| {
+                        character.is_whitespace() || "[]{}();".contains(character)
+                    })
+                    .unwrap_or(text.len());
+                let token = &text[..end];
+                text = &text[end..];
+
+                if variation_depth > 0 || token.starts_with('$') {
+                    continue;
+                }
+
+                if RESULTS.contains(&token) {
+                    let game = match game.take() {
+                        Some(game) => game,
+                        None => starting_position(&tags)?,
+                    };
+                    games.push(PgnGame {
+                        tags: std::mem::take(&mut tags),
+                        game,
+                        comments: std::mem::take(&mut comments),
+                        result: token.to_string(),
+                    });
+                    continue;
+                }
+
+                let token = strip_move_number(token);
+                if token.is_empty() {
+                    continue;
+                }
+
+                let game = match &mut game {
+                    Some(game) => game,
+                    None => game.insert(starting_position(&tags)?),
+                };
+                if game.len() >= MAX_GAME_LENGTH {
+                    bail!("Game is longer than {} plies", MAX_GAME_LENGTH);
+                }
+                let _move = Move::from_san(token, game)
+                    .with_context(|| format!("Invalid move at ply {}", game.len()))?;
+                game.push_history(_move);
+                comments.push(None);
+            }
+        }
+    }
+
+    if variation_depth > 0 {
+        bail!("Unterminated variation");
+    }
+
+    // The last game may lack a result token
+    if game.is_some() || !tags.is_empty() {
+        games.push(PgnGame {
+            game: match game {
+                Some(game) => game,
+                None => starting_position(&tags)?,
+            },
+            tags,
+            comments,
+            result: String::from("*"),
+        });
+    }
+
+    Ok(games)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_games() {
+        let pgn = r#"
+[Event "Casual \"blitz\" game"]
+[White "Anderssen"]
+[Black "Kieseritzky"]
+[Result "1-0"]
+
+1. e4 e5 2. f4 exf4 3. Bc4 Qh4+ {Black loses the right to castle} 4. Kf1 b5?!
+5. Bxb5 Nf6 $1 6. Nf3 Qh6 (6... Qh5 7. d3 (7. Nc3 Bb7) Nh5) 7. d3 Nh5 1-0
+
+[Event "From a position"]
+[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 w - - 0 40"]
+
+40.e4 Kd7 41. Kf2 ; line comment 1-0
+% escaped line Kc6
+41... Kc6 *
+"#;
+
+        let games = read_pgn(pgn).unwrap();
+        assert_eq!(games.len(), 2);
+
+        assert_eq!(games[0].tag("Event"), Some("Casual \"blitz\" game"));
+        assert_eq!(games[0].tag("White"), Some("Anderssen"));
+        assert_eq!(games[0].result, "1-0");
+        assert_eq!(games[0].game.move_stack.len(), 14);
+        assert_eq!(
+            games[0].game.to_fen(),
+            "rnb1kb1r/p1pp1ppp/7q/1B5n/4Pp2/3P1N2/PPP3PP/RNBQ1K1R w kq - 1 8"
+        );
+
+        assert_eq!(games[1].result, "*");
+        assert_eq!(games[1].game.to_fen(), "8/8/2k5/8/4P3/8/5K2/8 w - - 3 42");
+    }
+
+    #[test]
+    fn write_games() {
+        let mut game = ChessGame::default();
+        for _ in 0..6 {
+            for move_str in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+                game.push_history(Move::from_uci_notation(move_str, &game).unwrap());
+            }
+        }
+        let mut pgn_game = PgnGame::new(game, "1/2-1/2");
+        pgn_game.set_tag("White", "daniel \"the engine\"");
+        pgn_game.set_tag("Opening", "Knight dance");
+        pgn_game.comments[0] = Some(engine_comment(35, 12, Duration::from_millis(1250)));
+
+        let pgn = pgn_game.to_pgn();
+        assert!(pgn.starts_with(concat!(
+            "[Event \"?\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]\n",
+            "[White \"daniel \\\"the engine\\\"\"]\n[Black \"?\"]\n[Result \"1/2-1/2\"]\n",
+            "[Opening \"Knight dance\"]\n\n1. Nf3 {+0.35/12 1.250s} Nf6 2. Ng1 Ng8",
+        )));
+        assert!(pgn.lines().all(|line| line.len() <= LINE_WIDTH));
+        assert_eq!(
+            engine_comment(31995, 7, Duration::from_millis(20)),
+            "+M3/7 0.020s"
+        );
+        assert_eq!(
+            engine_comment(-31996, 7, Duration::from_millis(20)),
+            "-M2/7 0.020s"
+        );
+        assert!(pgn.trim_end().ends_with("12. Ng1 Ng8 1/2-1/2"));
+
+        let read = read_pgn(&pgn).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].tag("White"), Some("daniel \"the engine\""));
+        assert_eq!(read[0].comments, pgn_game.comments);
+        assert_eq!(read[0].game.move_stack, pgn_game.game.move_stack);
+
+        let mut game = ChessGame::new("4k3/8/8/8/8/8/4P3/4K3 b - - 0 40").unwrap();
+        game.push_history(Move::from_uci_notation("e8d7", &game).unwrap());
+        let pgn = PgnGame::new(game, "*").to_pgn();
+        assert!(pgn.contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 40\"]\n"));
+        assert!(pgn.ends_with("\n\n40... Kd7 *\n"));
+    }
+
+    #[test]
+    fn invalid_games() {
+        assert!(read_pgn("1. e4 e5 2. Ke3").is_err());
+        assert!(read_pgn("1. e4 (1. d4 e5").is_err());
+        assert!(read_pgn("[Event \"Unterminated]").is_err());
+        assert!(read_pgn("[FEN \"not a fen\"]\n\n1. e4").is_err());
+    }
+}
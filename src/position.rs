@@ -44,27 +44,10 @@ impl Position {
         self.1
     }
 
+    /// Inverse of as_usize, only the lower 6 bits of index are used
     #[inline]
-    pub fn add(self, delta: (i8, i8)) -> Option<Self> {
-        let row = self.0 + delta.0;
-        let col = self.1 + delta.1;
-        if (0..8).contains(&row) && (0..8).contains(&col) {
-            Some(Self(row, col))
-        } else {
-            None
-        }
-    }
-
-    /// # Safety
-    /// Same as self.add, but unchecked
-    ///
-    /// Caller must guarantee that return value is valid
-    #[inline]
-    pub unsafe fn add_unsafe(self, delta: (i8, i8)) -> Self {
-        let row = self.0 + delta.0;
-        let col = self.1 + delta.1;
-        debug_assert!((0..8).contains(&row) && (0..8).contains(&col));
-        Self(row, col)
+    pub fn from_usize(index: usize) -> Self {
+        Self((index >> 3) as i8 & 7, index as i8 & 7)
     }
 
     /// Returns the index this position would take in a linear board array
@@ -1,133 +1,470 @@
 use std::{
-    cmp::Ordering,
     sync::{
         atomic::{self, AtomicBool},
         Arc,
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use arrayvec::ArrayVec;
 
-use crate::{chess_game::ChessGame, move_struct::Move, piece::Score};
+use crate::{
+    chess_game::{ChessGame, Players},
+    move_struct::Move,
+    options::EngineOptions,
+    piece::{Piece, PieceTypes, Score},
+    transposition_table::{Bound, TableEntry, TranspositionTable},
+};
 
-/// Very simple comparing function to determine which moves are likely to be better
-fn simple_move_compare(a: &Move, b: &Move) -> Ordering {
-    match a {
+/// Most valuable victim, least valuable attacker
+///
+/// Captures get a higher score the more valuable the captured piece is,
+/// and for the same captured piece, the less valuable the capturing piece is
+fn capture_score(_move: &Move) -> i16 {
+    match _move {
         Move::Normal {
-            captured_piece: capture_a,
-            piece: piece_a,
+            piece,
+            captured_piece: Some(captured_piece),
+            ..
+        } => captured_piece.material_value() as i16 * 128 - piece.material_value() as i16,
+        Move::Promotion {
+            new_piece,
+            captured_piece,
+            owner,
             ..
-        } => match b {
-            Move::Normal {
-                captured_piece: capture_b,
-                piece: piece_b,
-                ..
-            } => {
-                if let Some(cap_piece_a) = capture_a {
-                    if let Some(cap_piece_b) = capture_b {
-                        if cap_piece_a != cap_piece_b {
-                            return cap_piece_a.piece_type.cmp(&cap_piece_b.piece_type);
+        } => {
+            let new_piece = Piece {
+                piece_type: *new_piece,
+                owner: *owner,
+            };
+            let captured_value = captured_piece.map_or(0, |piece| piece.material_value());
+            (new_piece.material_value() + captured_value) as i16 * 128 - 1
+        }
+        Move::EnPassant { .. } => 127,
+        _ => 0,
+    }
+}
+
+/// Captures which are unlikely to lose material: the captured piece is worth
+/// at least as much as the capturing one, or it isn't defended
+fn is_good_capture(game: &ChessGame, _move: &Move) -> bool {
+    match *_move {
+        Move::Normal {
+            piece,
+            end,
+            captured_piece: Some(captured_piece),
+            ..
+        } => {
+            captured_piece.material_value() >= piece.material_value()
+                || !game.is_targeted(end, piece.owner)
+        }
+        _ => true,
+    }
+}
+
+/// Scores of quiet moves by how often they caused a beta cutoff,
+/// indexed by the player making them and their start and end squares
+///
+/// Source: https://www.chessprogramming.org/History_Heuristic
+struct History([[[i32; 64]; 64]; 2]);
+
+/// History scores stay between -MAX_HISTORY and MAX_HISTORY
+const MAX_HISTORY: i32 = 16384;
+
+impl History {
+    fn get(&self, player: Players, _move: Move) -> i32 {
+        let (start, end) = _move.start_end();
+        self.0[player.as_usize()][start.as_usize()][end.as_usize()]
+    }
+
+    /// The closer the score already is to the limit the less it changes (history gravity),
+    /// so moves which stopped being good are quickly forgotten
+    fn update(&mut self, player: Players, _move: Move, bonus: i32) {
+        let (start, end) = _move.start_end();
+        let score = &mut self.0[player.as_usize()][start.as_usize()][end.as_usize()];
+        let bonus = bonus.clamp(-MAX_HISTORY, MAX_HISTORY);
+        *score += bonus - *score * bonus.abs() / MAX_HISTORY;
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum Stage {
+    HashMove,
+    GenerateCaptures,
+    GoodCaptures,
+    Refutations,
+    GenerateQuiets,
+    Quiets,
+    BadCaptures,
+    Evasions,
+    Done,
+}
+
+/// Yields the legal moves of a position in the order they are most likely to be good:
+/// hash move, good captures, killer moves and countermove, quiet moves by their history,
+/// then bad captures
+///
+/// Moves are generated lazily, so a node which gets a cutoff from the hash move
+/// or a capture never generates the quiet moves, and each of them is scored,
+/// with the best one remaining picked every time instead of sorting them all
+///
+/// When in check all the evasions are generated at once instead, since there are few of them
+struct MovePicker {
+    stage: Stage,
+    hash_move: Option<Move>,
+    /// Quiet moves which refuted other moves, the killers then the countermove
+    refutations: ArrayVec<Move, 3>,
+    moves: ArrayVec<Move, 256>,
+    scores: ArrayVec<i32, 256>,
+    bad_captures: ArrayVec<Move, 256>,
+    index: usize,
+}
+
+impl MovePicker {
+    fn new(
+        game: &ChessGame,
+        hash_move: Option<Move>,
+        killers: [Option<Move>; 2],
+        countermove: Option<Move>,
+        history: &History,
+    ) -> Self {
+        let mut picker = Self {
+            stage: Stage::HashMove,
+            hash_move,
+            refutations: ArrayVec::new(),
+            moves: ArrayVec::new(),
+            scores: ArrayVec::new(),
+            bad_captures: ArrayVec::new(),
+            index: 0,
+        };
+        for _move in killers.into_iter().chain([countermove]).flatten() {
+            if Some(_move) != hash_move && !picker.refutations.contains(&_move) {
+                picker.refutations.push(_move);
+            }
+        }
+
+        if game.is_in_check() {
+            game.get_evasions(&mut picker.moves);
+            picker.score_moves(|_move| {
+                if Some(_move) == hash_move {
+                    i32::MAX
+                } else if _move.is_quiet() {
+                    history.get(game.current_player, _move)
+                } else {
+                    MAX_HISTORY + capture_score(&_move) as i32
+                }
+            });
+            picker.stage = Stage::Evasions;
+        }
+
+        picker
+    }
+
+    /// If in check and there is a single legal move, returns it
+    fn single_evasion(&self) -> Option<Move> {
+        if self.stage == Stage::Evasions && self.moves.len() == 1 {
+            return self.moves.first().copied();
+        }
+        None
+    }
+
+    /// The move is searched early since it refuted other moves
+    fn is_refutation(&self, _move: Move) -> bool {
+        self.refutations.contains(&_move)
+    }
+
+    fn score_moves(&mut self, score: impl Fn(Move) -> i32) {
+        self.scores.clear();
+        self.scores
+            .extend(self.moves.iter().map(|_move| score(*_move)));
+        self.index = 0;
+    }
+
+    /// Takes the remaining move with the highest score
+    fn pick_best(&mut self) -> Option<Move> {
+        let best = (self.index..self.moves.len()).max_by_key(|&index| self.scores[index])?;
+        self.moves.swap(self.index, best);
+        self.scores.swap(self.index, best);
+        self.index += 1;
+        Some(self.moves[self.index - 1])
+    }
+
+    fn next(&mut self, game: &ChessGame, history: &History) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::HashMove => {
+                    self.stage = Stage::GenerateCaptures;
+                    match self.hash_move {
+                        Some(hash_move) if game.is_legal(hash_move) => return Some(hash_move),
+                        _ => self.hash_move = None,
+                    }
+                }
+                Stage::GenerateCaptures => {
+                    game.get_captures(&mut self.moves);
+                    self.score_moves(|_move| capture_score(&_move) as i32);
+                    self.stage = Stage::GoodCaptures;
+                }
+                Stage::GoodCaptures => {
+                    while let Some(_move) = self.pick_best() {
+                        if Some(_move) == self.hash_move {
+                            continue;
+                        }
+                        if !is_good_capture(game, &_move) {
+                            self.bad_captures.push(_move);
+                            continue;
+                        }
+                        return Some(_move);
+                    }
+                    self.index = 0;
+                    self.stage = Stage::Refutations;
+                }
+                Stage::Refutations => {
+                    while let Some(&_move) = self.refutations.get(self.index) {
+                        self.index += 1;
+                        if _move.is_quiet() && game.is_legal(_move) {
+                            return Some(_move);
+                        }
+                    }
+                    self.stage = Stage::GenerateQuiets;
+                }
+                Stage::GenerateQuiets => {
+                    game.get_quiet_moves(&mut self.moves);
+                    self.score_moves(|_move| history.get(game.current_player, _move));
+                    self.stage = Stage::Quiets;
+                }
+                Stage::Quiets => {
+                    while let Some(_move) = self.pick_best() {
+                        if Some(_move) == self.hash_move || self.is_refutation(_move) {
+                            continue;
                         }
-                        return piece_b.piece_type.cmp(&piece_a.piece_type);
+                        return Some(_move);
+                    }
+                    self.index = 0;
+                    self.stage = Stage::BadCaptures;
+                }
+                Stage::BadCaptures => {
+                    if let Some(&_move) = self.bad_captures.get(self.index) {
+                        self.index += 1;
+                        return Some(_move);
                     }
-                    return Ordering::Less;
-                } else if capture_b.is_some() {
-                    return Ordering::Greater;
+                    self.stage = Stage::Done;
                 }
-                piece_a.piece_type.cmp(&piece_b.piece_type)
+                Stage::Evasions => return self.pick_best(),
+                Stage::Done => return None,
             }
-            Move::Promotion { .. } => Ordering::Greater,
-            _ => Ordering::Less,
-        },
-        Move::Promotion { .. } => Ordering::Less,
-        _ => Ordering::Greater,
+        }
     }
 }
 
-fn quiescence_search(game: &mut ChessGame, mut alpha: Score, beta: Score) -> Score {
-    let current_score = game.score * (game.current_player as Score);
-    alpha = alpha.max(current_score);
+/// Data of a single ply of the search, indexed by the number of moves made since the root
+#[derive(Clone, Copy, Default)]
+struct StackEntry {
+    /// Quiet moves which caused a beta cutoff at this ply, the most recent first
+    killers: [Option<Move>; 2],
+    /// Move being searched, None for a null move
+    current_move: Option<Move>,
+}
 
-    if alpha >= beta {
-        return alpha;
-    }
+/// Maximum number of moves made since the root of the search tree, the search depth is
+/// limited to it as well, since iterations finish instantly in positions with few moves
+const MAX_PLY: u8 = 64;
 
-    let player = game.current_player;
-    let mut moves = ArrayVec::new();
+/// Data shared between all the nodes of a search, and between its iterations
+pub struct SearchContext<'a> {
+    should_stop: &'a AtomicBool,
+    tt: &'a TranspositionTable,
+    /// Grown as deeper plies are reached
+    stack: Vec<StackEntry>,
+    history: Box<History>,
+    /// The quiet move which last refuted a move, indexed by its start and end squares
+    ///
+    /// Source: https://www.chessprogramming.org/Countermove_Heuristic
+    countermoves: Box<[[Option<Move>; 64]; 64]>,
+    /// Number of positions visited, including by the quiescence search
+    pub nodes: u64,
+    /// The search halts once this many nodes were visited
+    max_nodes: u64,
+    /// If not empty, only these moves are searched at the root
+    search_moves: Vec<Move>,
+    /// Root moves which aren't searched, since their line was already reported
+    excluded_moves: Vec<Move>,
+    /// Most moves made since the root in any node, including by the quiescence search
+    seldepth: u8,
+    /// Set for the search reporting to the GUI, which prints the root move being searched
+    report_root_moves: bool,
+    start: Instant,
+    /// Triangular table of the best lines, the one of every node starts with its best move
+    /// followed by the line of the node after it
+    ///
+    /// Source: https://www.chessprogramming.org/Triangular_PV-Table
+    pv_table: [ArrayVec<Move, { MAX_PLY as usize }>; MAX_PLY as usize + 1],
+    /// Best line of the previous iteration, searched first by the next one
+    previous_pv: Vec<Move>,
+    /// The current node is on the best line of the previous iteration
+    follow_pv: bool,
+    /// Score of the previous iteration, the next one is expected to be close to it
+    previous_score: Option<Score>,
+    /// Cleared before searching a node which must not make a null move
+    null_move_allowed: bool,
+}
 
-    // It is possible for the game to be a stalemate, but be recognized as a checkmate
-    // Because we don't validate the king's moves there due to performance reasons
-    game.get_moves(&mut moves, false);
+impl<'a> SearchContext<'a> {
+    pub fn new(should_stop: &'a AtomicBool, tt: &'a TranspositionTable) -> Self {
+        Self {
+            should_stop,
+            tt,
+            stack: vec![],
+            history: Box::new(History([[[0; 64]; 64]; 2])),
+            countermoves: Box::new([[None; 64]; 64]),
+            nodes: 0,
+            max_nodes: u64::MAX,
+            search_moves: vec![],
+            excluded_moves: vec![],
+            seldepth: 0,
+            report_root_moves: false,
+            start: Instant::now(),
+            pv_table: std::array::from_fn(|_| ArrayVec::new()),
+            previous_pv: vec![],
+            follow_pv: false,
+            previous_score: None,
+            null_move_allowed: true,
+        }
+    }
 
-    if moves.is_empty() {
-        if game.king_exists(player) && !game.is_targeted(game.get_king_position(player), player) {
-            return 0;
-        } else {
-            // The earlier the mate the worse the score for the losing player
-            return Score::MIN + 100 + game.len() as Score;
+    fn stack(&mut self, real_depth: u8) -> &mut StackEntry {
+        let ply = real_depth as usize;
+        if ply >= self.stack.len() {
+            self.stack.resize(ply + 1, StackEntry::default());
         }
+        &mut self.stack[ply]
     }
 
-    for _move in &moves {
-        let _move = *_move;
+    /// The move which refuted the previous move the last time, if any
+    fn countermove(&mut self, real_depth: u8) -> Option<Move> {
+        let previous_move = self.stack(real_depth.checked_sub(1)?).current_move?;
+        let (start, end) = previous_move.start_end();
+        self.countermoves[start.as_usize()][end.as_usize()]
+    }
 
-        if !_move.is_tactical_move() {
-            continue;
+    /// Rewards the quiet move which caused a beta cutoff, and penalizes the quiet moves
+    /// searched before it, which didn't
+    fn update_quiet_stats(
+        &mut self,
+        player: Players,
+        real_depth: u8,
+        remaining_depth: u8,
+        _move: Move,
+        searched_quiets: &[Move],
+    ) {
+        let bonus = remaining_depth as i32 * remaining_depth as i32;
+        self.history.update(player, _move, bonus);
+        for &quiet in searched_quiets {
+            self.history.update(player, quiet, -bonus);
         }
 
-        game.push(_move);
-        let score = -quiescence_search(game, -beta, -alpha);
-        game.pop(_move);
+        let killers = &mut self.stack(real_depth).killers;
+        if killers[0] != Some(_move) {
+            killers[1] = killers[0];
+            killers[0] = Some(_move);
+        }
 
-        if score > alpha {
-            alpha = score;
+        if let Some(previous_move) = real_depth
+            .checked_sub(1)
+            .and_then(|ply| self.stack(ply).current_move)
+        {
+            let (start, end) = previous_move.start_end();
+            self.countermoves[start.as_usize()][end.as_usize()] = Some(_move);
         }
+    }
 
-        if alpha >= beta {
-            break;
+    /// The best line found by the last search from the root
+    pub fn pv(&self) -> &[Move] {
+        &self.pv_table[0]
+    }
+
+    fn clear_pv(&mut self, real_depth: u8) {
+        if let Some(line) = self.pv_table.get_mut(real_depth as usize) {
+            line.clear();
         }
     }
 
-    alpha
+    /// The line of the node becomes the move followed by the line of the next node
+    fn update_pv(&mut self, real_depth: u8, _move: Move) {
+        let ply = real_depth as usize;
+        if ply >= MAX_PLY as usize {
+            return;
+        }
+
+        let (lines, next_lines) = self.pv_table.split_at_mut(ply + 1);
+        let line = &mut lines[ply];
+        line.clear();
+        line.push(_move);
+        line.extend(
+            next_lines[0]
+                .iter()
+                .copied()
+                .take(MAX_PLY as usize - ply - 1),
+        );
+    }
 }
 
-/// This function exists in order to improve the performance of the search algorithm
-/// It's the same as get_best_move_score but with a depth always equal to 1
+/// Searches only the captures, so that the position is evaluated once it is quiet
 ///
-/// Explanation: due to the nature of the search tree (exponential growth), the majority
-/// of the time is spent in this function, so it's eliminating unnecessary branches
-fn get_best_move_score_depth_1(game: &mut ChessGame, mut alpha: Score, beta: Score) -> Score {
-    let player = game.current_player;
-    let mut moves = ArrayVec::new();
-    game.get_moves(&mut moves, false);
+/// When in check all the evasions are searched instead, since standing pat isn't an option,
+/// and on the first ply quiet moves giving check are searched as well, to not miss mates
+fn quiescence_search(
+    game: &mut ChessGame,
+    context: &mut SearchContext,
+    // Moves made since root of the search tree
+    real_depth: u8,
+    mut alpha: Score,
+    beta: Score,
+    try_checks: bool,
+) -> Score {
+    context.nodes += 1;
+    context.seldepth = context.seldepth.max(real_depth);
+    context.clear_pv(real_depth);
 
-    if moves.is_empty() {
-        if !game.is_targeted(game.get_king_position(player), player) {
-            return 0;
-        } else {
-            // The earlier the mate the worse the score for the losing player
-            return Score::MIN + 100 + game.len() as Score;
+    let in_check = game.is_in_check();
+    let mut moves = ArrayVec::new();
+    if in_check {
+        game.get_evasions(&mut moves);
+        if moves.is_empty() {
+            return mated_score(real_depth);
+        }
+    } else {
+        let current_score = game.score * (game.current_player as Score);
+        alpha = alpha.max(current_score);
+        if alpha >= beta {
+            return alpha;
         }
-    } else if moves.len() == 1 {
-        // If there is only one move available push it and don't decrease depth
-        let _move = moves[0];
-        game.push(_move);
-        let score = -get_best_move_score_depth_1(game, -beta, -alpha);
-        game.pop(_move);
 
-        return score;
+        game.get_captures(&mut moves);
+        if try_checks {
+            let mut checks = ArrayVec::<Move, 256>::new();
+            game.get_quiet_checks(&mut checks);
+            moves.extend(checks);
+        }
     }
 
+    moves.sort_unstable_by_key(|_move| -capture_score(_move));
+
     for _move in &moves {
         let _move = *_move;
+
+        // Captures likely to lose material aren't worth it, unless escaping check
+        if !in_check && !_move.is_quiet() && !_move.is_tactical_move() {
+            continue;
+        }
+
         game.push(_move);
-        let score = -quiescence_search(game, -beta, -alpha);
+        let score = -quiescence_search(game, context, real_depth + 1, -beta, -alpha, false);
         game.pop(_move);
 
         if score > alpha {
             alpha = score;
+            context.update_pv(real_depth, _move);
         }
 
         if alpha >= beta {
@@ -138,238 +475,1004 @@ fn get_best_move_score_depth_1(game: &mut ChessGame, mut alpha: Score, beta: Sco
     alpha
 }
 
+/// Null move pruning is only tried with at least this many moves left to search
+const NULL_MOVE_MIN_DEPTH: u8 = 3;
+
+/// Null move cutoffs are verified by a normal search with at least this many moves left
+const NULL_MOVE_VERIFICATION_DEPTH: u8 = 8;
+
+/// Late move pruning is only done with at most this many moves left to search
+const LATE_MOVE_PRUNING_DEPTH: u8 = 3;
+
+/// Number of moves searched before the late quiet moves are pruned
+fn late_move_pruning_count(remaining_depth: u8) -> usize {
+    3 + remaining_depth as usize * remaining_depth as usize
+}
+
+/// How many moves less to search a late quiet move, growing with both the depth
+/// and the number of moves searched before, at least one move is always left to search
+///
+/// Source: https://www.chessprogramming.org/Late_Move_Reductions
+fn late_move_reduction(remaining_depth: u8, move_count: usize) -> u8 {
+    if remaining_depth < 3 || move_count < 4 {
+        return 0;
+    }
+    let reduction = 0.75 + (remaining_depth as f32).ln() * (move_count as f32).ln() / 2.25;
+    (reduction as u8).min(remaining_depth - 2)
+}
+
+/// Positions where the current player only has pawns left are often zugzwang
+fn has_non_pawn_material(game: &ChessGame) -> bool {
+    [
+        PieceTypes::Knight,
+        PieceTypes::Bishop,
+        PieceTypes::Rook,
+        PieceTypes::Queen,
+    ]
+    .into_iter()
+    .any(|piece_type| game.piece_bitboard(piece_type, game.current_player) != 0)
+}
+
+/// Score of the current player getting checkmated at the root of the search
+const MATE_SCORE: Score = 32000;
+
+/// Mate scores are at most this many plies away from MATE_SCORE,
+/// which is more than the quiescence search can ever reach
+const MAX_MATE_PLIES: Score = 1000;
+
+/// The earlier the mate the worse the score for the losing player,
+/// so the winning one goes for the shortest mate
+fn mated_score(real_depth: u8) -> Score {
+    -MATE_SCORE + real_depth as Score
+}
+
+/// Mate scores are stored relative to the position instead of to the root,
+/// since the same position can be reached at different depths
+fn score_to_tt(score: Score, real_depth: u8) -> Score {
+    match moves_to_mate(score) {
+        Some(_) if score > 0 => score + real_depth as Score,
+        Some(_) => score - real_depth as Score,
+        None => score,
+    }
+}
+
+fn score_from_tt(score: Score, real_depth: u8) -> Score {
+    match moves_to_mate(score) {
+        Some(_) if score > 0 => score - real_depth as Score,
+        Some(_) => score + real_depth as Score,
+        None => score,
+    }
+}
+
 /// Core function of the alpha beta search algorithm
 /// It halts early and returns None if the should_stop flag is set
 /// Otherwise returns the best score for the current player
 fn get_best_move_score(
     game: &mut ChessGame,
-    should_stop: &AtomicBool,
+    context: &mut SearchContext,
     // Moves left to search
     remaining_depth: u8,
     // Moves made since root of the search tree
     real_depth: u8,
     mut alpha: Score,
-    beta: Score,
-    killer_moves: &mut [Option<Move>],
+    mut beta: Score,
 ) -> Option<Score> {
-    if should_stop.load(atomic::Ordering::Relaxed) {
+    // Consumed first, so that the flag only ever applies to the node it was set for
+    let null_move_allowed = std::mem::replace(&mut context.null_move_allowed, true);
+
+    if context.should_stop.load(atomic::Ordering::Relaxed) || context.nodes >= context.max_nodes {
         // Halt the search early
         return None;
     }
+    context.nodes += 1;
+    context.seldepth = context.seldepth.max(real_depth);
+    context.clear_pv(real_depth);
 
-    if remaining_depth == 1 {
-        return Some(get_best_move_score_depth_1(game, alpha, beta));
-    } else if remaining_depth == 0 {
-        return Some(game.score * (game.current_player as Score));
+    // A repetition inside the search is scored as a draw already the second time the position
+    // occurs, since if repeating was the best choice once it will be again, while
+    // one of a position played before the root needs a third occurrence to be a draw
+    let repetition = game.repetitions_within((real_depth as usize).saturating_sub(1)) > 0
+        || game.repetitions() >= 2;
+    if repetition || game.is_insufficient_material() || game.is_fifty_move_draw() {
+        return Some(0);
     }
 
-    let player = game.current_player;
-    let mut moves = ArrayVec::new();
-    game.get_moves(&mut moves, true);
+    // Mate distance pruning: no score here can beat getting mated right now
+    // or mating on the next move, so with a shorter mate already found there is no hope
+    alpha = alpha.max(mated_score(real_depth));
+    beta = beta.min(-mated_score(real_depth + 1));
+    if alpha >= beta {
+        return Some(alpha);
+    }
 
-    if moves.is_empty() {
-        if !game.is_targeted(game.get_king_position(player), player) {
-            return Some(0);
-        } else {
-            // The earlier the mate the worse the score for the losing player
-            return Some(Score::MIN + 100 + game.len() as Score);
+    // Check extension: positions in check are searched one move deeper,
+    // since there are few evasions and they often lead to forced lines
+    let in_check = game.is_in_check();
+    let remaining_depth = remaining_depth + in_check as u8;
+
+    if remaining_depth == 0 || real_depth >= MAX_PLY {
+        return Some(quiescence_search(
+            game, context, real_depth, alpha, beta, true,
+        ));
+    }
+
+    // The full window is wider than a Score can hold
+    let is_pv_node = beta as i32 - alpha as i32 > 1;
+
+    let hash = game.hash();
+    let mut hash_move = None;
+    if let Some(mut entry) = context.tt.probe(hash) {
+        entry.score = score_from_tt(entry.score, real_depth);
+        // A search at least as deep was already done for this position,
+        // but PV nodes are searched anyway, so that the PV isn't cut short
+        if entry.depth >= remaining_depth && !is_pv_node {
+            match entry.bound {
+                Bound::Exact => return Some(entry.score),
+                Bound::Lower if entry.score >= beta => return Some(entry.score),
+                Bound::Upper if entry.score <= alpha => return Some(entry.score),
+                _ => (),
+            }
+        }
+        hash_move = entry.best_move;
+    }
+    let original_alpha = alpha;
+
+    // Null move pruning: if passing the turn still scores at least beta, a real move
+    // would too, which is only wrong in zugzwang, so it is avoided where zugzwang is likely
+    // Not in PV nodes, where the exact score matters, and never twice in a row
+    if null_move_allowed
+        && remaining_depth >= NULL_MOVE_MIN_DEPTH
+        && !is_pv_node
+        && !context.follow_pv
+        && moves_to_mate(beta).is_none()
+        && game.score * (game.current_player as Score) >= beta
+        && !in_check
+        && has_non_pawn_material(game)
+    {
+        // Deeper searches can afford a bigger reduction
+        let reduction = if remaining_depth > 6 { 3 } else { 2 };
+        let null_depth = remaining_depth.saturating_sub(1 + reduction);
+
+        context.stack(real_depth).current_move = None;
+        game.push_null();
+        context.null_move_allowed = false;
+        let score =
+            -get_best_move_score(game, context, null_depth, real_depth + 1, -beta, -beta + 1)?;
+        game.pop_null();
+
+        if score >= beta {
+            // Close to the root a mistake costs more, so the cutoff is verified
+            // by a reduced search of this node without a null move
+            if remaining_depth < NULL_MOVE_VERIFICATION_DEPTH {
+                return Some(beta);
+            }
+            context.null_move_allowed = false;
+            let score = get_best_move_score(game, context, null_depth, real_depth, beta - 1, beta)?;
+            if score >= beta {
+                return Some(beta);
+            }
         }
-    } else if moves.len() == 1 {
-        // If there is only one move available push it and don't decrease depth
-        let _move = moves[0];
+    }
+
+    // Along the best line of the previous iteration, its move is searched first
+    if context.follow_pv {
+        let pv_move = context.previous_pv.get(real_depth as usize).copied();
+        context.follow_pv = pv_move.is_some();
+        hash_move = pv_move.or(hash_move);
+    }
+
+    let killers = context.stack(real_depth).killers;
+    let countermove = context.countermove(real_depth);
+    let mut picker = MovePicker::new(game, hash_move, killers, countermove, &context.history);
+
+    if let Some(_move) = picker.single_evasion() {
+        context.stack(real_depth).current_move = Some(_move);
+        // If there is only one move available don't bother ordering or storing it,
+        // the depth was already extended for being in check
         game.push(_move);
         let score = -get_best_move_score(
             game,
-            should_stop,
-            remaining_depth,
+            context,
+            remaining_depth - 1,
             real_depth + 1,
             -beta,
             -alpha,
-            killer_moves,
         )?;
         game.pop(_move);
+        context.update_pv(real_depth, _move);
 
         return Some(score);
     }
 
-    // Before sorting test killer move (and remove it from the list)
-
-    if let Some(killer_move) = killer_moves[real_depth as usize] {
-        let mut killer_move_index = None;
-        for (index, _move) in moves.iter().enumerate() {
-            if killer_move == *_move {
-                killer_move_index = Some(index);
-                break;
-            }
-        }
+    let mut best_move = None;
+    let mut move_count = 0;
+    let mut searched_quiets = ArrayVec::<Move, 256>::new();
+    while let Some(_move) = picker.next(game, &context.history) {
+        move_count += 1;
+        context.stack(real_depth).current_move = Some(_move);
+        game.push(_move);
 
-        if let Some(index) = killer_move_index {
-            let _move = moves[index];
-            moves.swap_pop(index);
+        // Quiet moves ordered late are unlikely to be good, unless they give check
+        let is_late_quiet = move_count > 1
+            && !in_check
+            && _move.is_quiet()
+            && !picker.is_refutation(_move)
+            && !game.is_in_check();
 
-            game.push(killer_move);
+        // Late move pruning: close to the leaves they are not searched at all,
+        // as long as some move already avoids getting mated
+        if is_late_quiet
+            && !is_pv_node
+            && remaining_depth <= LATE_MOVE_PRUNING_DEPTH
+            && move_count > late_move_pruning_count(remaining_depth)
+            && alpha > mated_score(MAX_PLY)
+        {
+            game.pop(_move);
+            continue;
+        }
 
-            let score = -get_best_move_score(
+        // The first move is expected to be the best, the others are searched
+        // with a null window to prove they are worse, and again if they are not
+        let score = if move_count == 1 {
+            -get_best_move_score(
                 game,
-                should_stop,
+                context,
                 remaining_depth - 1,
                 real_depth + 1,
                 -beta,
                 -alpha,
-                killer_moves,
-            )?;
-
-            game.pop(killer_move);
+            )?
+        } else {
+            // Late move reductions: late quiet moves are first searched less deeply,
+            // and only searched to the full depth if they turn out to be better
+            let reduction = if is_late_quiet {
+                late_move_reduction(remaining_depth, move_count)
+            } else {
+                0
+            };
 
-            if score > alpha {
-                alpha = score;
+            let mut score = -alpha - 1;
+            if reduction > 0 {
+                score = -get_best_move_score(
+                    game,
+                    context,
+                    remaining_depth - 1 - reduction,
+                    real_depth + 1,
+                    -alpha - 1,
+                    -alpha,
+                )?;
+            }
+            if reduction == 0 || score > alpha {
+                score = -get_best_move_score(
+                    game,
+                    context,
+                    remaining_depth - 1,
+                    real_depth + 1,
+                    -alpha - 1,
+                    -alpha,
+                )?;
+            }
+            if score > alpha && score < beta {
+                score = -get_best_move_score(
+                    game,
+                    context,
+                    remaining_depth - 1,
+                    real_depth + 1,
+                    -beta,
+                    -alpha,
+                )?;
             }
+            score
+        };
 
-            if alpha >= beta {
-                return Some(alpha);
+        game.pop(_move);
+        // Only the first move searched can be on the previous best line
+        context.follow_pv = false;
+
+        if score > alpha {
+            alpha = score;
+            best_move = Some(_move);
+            context.update_pv(real_depth, _move);
+        }
+
+        if alpha >= beta {
+            if _move.is_quiet() {
+                context.update_quiet_stats(
+                    game.current_player,
+                    real_depth,
+                    remaining_depth,
+                    _move,
+                    &searched_quiets,
+                );
             }
+            break;
+        }
+        if _move.is_quiet() {
+            searched_quiets.push(_move);
         }
     }
 
-    // We want to sort the moves best on the most likely ones to be good
-    if remaining_depth >= 5 {
-        moves.sort_by_cached_key(|a| {
-            game.push(*a);
-            let score = get_best_move_score(
-                game,
-                should_stop,
-                remaining_depth - 5,
-                real_depth + 1,
-                -beta,
-                -alpha,
-                killer_moves,
-            );
-            game.pop(*a);
-            score
-        });
-    } else if remaining_depth >= 2 {
-        moves.sort_unstable_by(simple_move_compare);
+    if move_count == 0 {
+        if !in_check {
+            return Some(0);
+        } else {
+            return Some(mated_score(real_depth));
+        }
     }
 
-    for _move in &moves {
-        let _move = *_move;
+    let bound = if alpha >= beta {
+        Bound::Lower
+    } else if alpha > original_alpha {
+        Bound::Exact
+    } else {
+        Bound::Upper
+    };
+    context.tt.store(
+        hash,
+        TableEntry {
+            best_move,
+            score: score_to_tt(alpha, real_depth),
+            depth: remaining_depth,
+            bound,
+        },
+    );
 
-        game.push(_move);
+    Some(alpha)
+}
 
-        let score = -get_best_move_score(
-            game,
-            should_stop,
-            remaining_depth - 1,
-            real_depth + 1,
-            -beta,
-            -alpha,
-            killer_moves,
-        )?;
+/// Width of the first aspiration window on each side of the score of the previous iteration
+const ASPIRATION_WINDOW: Score = 25;
+
+/// Searches the root moves within the window, the best one is searched first
+/// and the others only need to prove they are not better (principal variation search)
+///
+/// Returns the best move, which is None if all the moves fail low, and the score,
+/// which is bounded by the window (fail hard)
+fn search_root_moves(
+    game: &mut ChessGame,
+    context: &mut SearchContext,
+    moves: &[Move],
+    depth: u8,
+    mut alpha: Score,
+    beta: Score,
+) -> Option<(Option<Move>, Score)> {
+    let mut best_move = None;
+
+    for (index, &_move) in moves.iter().enumerate() {
+        // Only once the search takes a while, to not flood the GUI
+        if context.report_root_moves && context.start.elapsed() >= REPORT_ROOT_MOVES_AFTER {
+            println!(
+                "info depth {} currmove {} currmovenumber {} hashfull {}",
+                depth,
+                _move.uci_notation(),
+                index + 1,
+                context.tt.hashfull()
+            );
+        }
 
+        context.stack(0).current_move = Some(_move);
+        game.push(_move);
+        let score = if index == 0 {
+            -get_best_move_score(game, context, depth - 1, 1, -beta, -alpha)?
+        } else {
+            let score = -get_best_move_score(game, context, depth - 1, 1, -alpha - 1, -alpha)?;
+            if score > alpha && score < beta {
+                -get_best_move_score(game, context, depth - 1, 1, -beta, -alpha)?
+            } else {
+                score
+            }
+        };
         game.pop(_move);
+        context.follow_pv = false;
 
         if score > alpha {
             alpha = score;
+            best_move = Some(_move);
+            context.update_pv(0, _move);
         }
 
         if alpha >= beta {
-            killer_moves[real_depth as usize] = Some(_move);
             break;
         }
     }
 
-    Some(alpha)
+    Some((best_move, alpha))
 }
 
 /// This function is the entry point for the search algorithm
 /// It returns the best move, the score of the best move
 /// and a flag indicating if there is only one move available
+///
+/// The search starts with a narrow window around the score of the previous iteration,
+/// which is widened if the score falls outside of it (aspiration windows)
 pub fn get_best_move_entry(
     mut game: ChessGame,
-    should_stop: &AtomicBool,
+    context: &mut SearchContext,
     depth: u8,
 ) -> Option<(Option<Move>, Score, bool)> {
-    let mut moves = ArrayVec::new();
-    game.get_moves(&mut moves, true);
+    let mut moves = ArrayVec::<Move, 256>::new();
+    game.get_moves(&mut moves);
+    if !context.search_moves.is_empty() {
+        moves.retain(|_move| context.search_moves.contains(_move));
+    }
+    moves.retain(|_move| !context.excluded_moves.contains(_move));
+    let restricted = !context.search_moves.is_empty() || !context.excluded_moves.is_empty();
 
     // If there is only one move available don't bother searching
-    if moves.len() == 1 {
+    context.clear_pv(0);
+    if moves.len() == 1 && context.excluded_moves.is_empty() {
+        context.update_pv(0, moves[0]);
         return Some((moves.first().copied(), 0, true));
     }
+    if moves.is_empty() {
+        return Some((None, -Score::MAX, false));
+    }
 
-    let mut killer_moves = [None; 32];
-    let mut best_move = None;
-    let mut best_score = -Score::MAX;
+    // Search the best move of the previous iteration first, then its line
+    context.follow_pv = context.excluded_moves.is_empty() && !context.previous_pv.is_empty();
+    let previous_best_move = match context.previous_pv.first() {
+        Some(_move) if context.follow_pv => Some(*_move),
+        _ => context
+            .tt
+            .probe(game.hash())
+            .and_then(|entry| entry.best_move),
+    };
+    if let Some(hash_move) = previous_best_move {
+        if let Some(index) = moves.iter().position(|_move| *_move == hash_move) {
+            moves.swap(0, index);
+        }
+    }
 
-    // Prevent threefold repetition
-    if game.move_stack.len() >= 5
-        && game.move_stack[game.move_stack.len() - 1] == game.move_stack[game.move_stack.len() - 5]
-    {
-        let repetition_move = game.move_stack[game.move_stack.len() - 4];
+    let mut window = ASPIRATION_WINDOW;
+    let (mut alpha, mut beta) = match context.previous_score {
+        Some(score) if context.excluded_moves.is_empty() && moves_to_mate(score).is_none() => {
+            (score - window, score + window)
+        }
+        _ => (-Score::MAX, Score::MAX),
+    };
 
-        for (index, _move) in moves.iter().enumerate() {
-            if repetition_move == *_move {
-                moves.swap_remove(index);
-                break;
+    let (best_move, best_score) = loop {
+        let (best_move, score) = search_root_moves(&mut game, context, &moves, depth, alpha, beta)?;
+
+        if score <= alpha && alpha > -Score::MAX {
+            alpha = alpha.saturating_sub(window).max(-Score::MAX);
+        } else if score >= beta && beta < Score::MAX {
+            beta = beta.saturating_add(window);
+            // The move which failed high is the most likely to be the best one
+            if let Some(index) = moves.iter().position(|_move| Some(*_move) == best_move) {
+                moves[..=index].rotate_right(1);
             }
+        } else {
+            break (best_move, score);
         }
+        window = window.saturating_mul(2);
+    };
+
+    if context.excluded_moves.is_empty() {
+        context.previous_pv = context.pv().to_vec();
+        context.previous_score = Some(best_score);
     }
 
-    for _move in moves {
-        game.push(_move);
-        // Initially alpha == beta
-        let score = -get_best_move_score(
-            &mut game,
-            should_stop,
-            depth - 1,
-            1,
-            Score::MIN + 1,
-            -best_score,
-            &mut killer_moves,
-        )?;
+    // With restricted root moves the score is not the real one of the position
+    if !restricted {
+        context.tt.store(
+            game.hash(),
+            TableEntry {
+                best_move,
+                score: best_score,
+                depth,
+                bound: Bound::Exact,
+            },
+        );
+    }
 
-        game.pop(_move);
-        if score > best_score {
-            best_score = score;
-            best_move = Some(_move);
+    Some((best_move, best_score, false))
+}
+
+/// Time after which the root moves are reported while they are searched
+const REPORT_ROOT_MOVES_AFTER: Duration = Duration::from_secs(1);
+
+/// The best move found by a search, with its score, the depth it was searched to
+/// and the line expected to follow, starting with the best move
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub best_move: Move,
+    pub score: Score,
+    pub depth: u8,
+    pub pv: Vec<Move>,
+}
+
+/// Number of moves the remaining time is divided between, when not given by the GUI
+const DEFAULT_MOVES_TO_GO: u32 = 50;
+
+/// When to stop searching, the search stops as soon as any of the limits is reached
+#[derive(Clone, Debug, Default)]
+pub struct SearchLimits {
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    /// Exact time to spend on the move
+    pub move_time: Option<Duration>,
+    /// Time left on the clock of the current player
+    pub time: Option<Duration>,
+    pub increment: Option<Duration>,
+    /// Moves left until the next time control
+    pub moves_to_go: Option<u32>,
+    /// Stop once a mate in this many moves is found
+    pub mate: Option<u8>,
+    /// Search until stopped from outside, ignoring the other limits
+    pub infinite: bool,
+    /// If not empty, only these moves are searched at the root
+    pub search_moves: Vec<Move>,
+}
+
+impl SearchLimits {
+    /// Time allotted to the move, None if the search isn't limited by time
+    ///
+    /// The move overhead is kept in reserve, to make sure we don't run out of time
+    pub fn time_for_move(&self, move_overhead: Duration) -> Option<Duration> {
+        if self.infinite {
+            return None;
+        }
+        if let Some(move_time) = self.move_time {
+            return Some(move_time);
         }
+
+        let time = self.time?;
+        let moves_to_go = self.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+        let time_for_move = time / moves_to_go + self.increment.unwrap_or_default();
+        Some(
+            time_for_move
+                .min(time.saturating_sub(move_overhead))
+                .max(Duration::from_millis(1)),
+        )
+    }
+}
+
+/// Number of moves until mate, positive if the current player mates and negative if they
+/// get mated, or None if the score isn't a mate score
+pub fn moves_to_mate(score: Score) -> Option<i16> {
+    let plies = MATE_SCORE - score.saturating_abs();
+    if !(0..=MAX_MATE_PLIES).contains(&plies) {
+        return None;
     }
 
-    Some((best_move, best_score, false))
+    if score > 0 {
+        Some((plies + 1) / 2)
+    } else {
+        Some(-plies / 2)
+    }
+}
+
+/// The score as sent to the GUI, in centipawns or in moves until mate
+fn uci_score(score: Score) -> String {
+    match moves_to_mate(score) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", score),
+    }
 }
 
 /// This function repeatedly calls get_best_move with increasing depth,
-/// until the time limit is reached, at which point it returns the best move found so far
-pub fn get_best_move_in_time(game: &ChessGame, duration: Duration) -> Option<Move> {
-    let mut found_move = None;
+/// until one of the limits is reached, at which point it returns the best move found so far
+///
+/// With more than one thread, the helper threads search the same position,
+/// only filling the shared transposition table for the main one (Lazy SMP)
+///
+/// The search can also be halted from outside through the should_stop flag,
+/// which should be a new one for every search, since it is set once the time is up
+pub fn get_best_move_in_time(
+    game: &ChessGame,
+    limits: &SearchLimits,
+    options: &EngineOptions,
+    tt: &TranspositionTable,
+    should_stop: Arc<AtomicBool>,
+) -> Option<SearchResult> {
+    tt.new_search();
 
     // Stop searching after the duration has passed
-    let should_stop = Arc::new(AtomicBool::new(false));
-    thread::spawn({
-        let should_stop = should_stop.clone();
-        move || {
-            thread::sleep(duration);
-            should_stop.store(true, atomic::Ordering::Relaxed);
-        }
-    });
-
-    for depth in 5.. {
-        let Some((best_move, best_score, is_only_move)) =
-            get_best_move_entry(game.clone(), should_stop.as_ref(), depth)
-        else {
-            return found_move;
-        };
+    if let Some(duration) = limits.time_for_move(options.move_overhead) {
+        thread::spawn({
+            let should_stop = should_stop.clone();
+            move || {
+                thread::sleep(duration);
+                should_stop.store(true, atomic::Ordering::Relaxed);
+            }
+        });
+    }
+
+    let mut max_depth = MAX_PLY;
+    let mut max_nodes = u64::MAX;
+    if !limits.infinite {
+        max_nodes = limits.nodes.unwrap_or(u64::MAX);
+        max_depth = limits.depth.unwrap_or(MAX_PLY).clamp(1, MAX_PLY);
+    }
+
+    // Set once the main thread is done
+    let helpers_should_stop = AtomicBool::new(false);
+    thread::scope(|scope| {
+        for helper in 1..options.threads {
+            let helpers_should_stop = &helpers_should_stop;
+            let game = game.clone();
+            scope.spawn(move || {
+                let mut context = SearchContext::new(helpers_should_stop, tt);
+                context.search_moves = limits.search_moves.clone();
+                // Starting at different depths makes the threads search different nodes
+                for depth in (1 + helper % 2) as u8..=max_depth {
+                    if get_best_move_entry(game.clone(), &mut context, depth).is_none() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let mut context = SearchContext::new(should_stop.as_ref(), tt);
+        context.search_moves = limits.search_moves.clone();
+        context.max_nodes = max_nodes;
+        context.report_root_moves = true;
+        let result = iterative_deepening(game, limits, options, &mut context, max_depth);
+
+        // An infinite search only answers once stopped
+        while limits.infinite && !should_stop.load(atomic::Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        helpers_should_stop.store(true, atomic::Ordering::Relaxed);
+        result
+    })
+}
+
+/// The iterations of the main thread, reporting the best lines after every one of them
+fn iterative_deepening(
+    game: &ChessGame,
+    limits: &SearchLimits,
+    options: &EngineOptions,
+    context: &mut SearchContext,
+    max_depth: u8,
+) -> Option<SearchResult> {
+    let mut result = None;
+
+    'deepening: for depth in 1..=max_depth {
+        // The best move of every line is left out when searching for the next one
+        context.excluded_moves.clear();
+        context.seldepth = 0;
+        let mut is_only_move = false;
+
+        for pv_index in 1..=options.multi_pv {
+            let Some((best_move, best_score, only_move)) =
+                get_best_move_entry(game.clone(), context, depth)
+            else {
+                break 'deepening;
+            };
+            let Some(best_move) = best_move else {
+                break;
+            };
+
+            if pv_index == 1 {
+                is_only_move = only_move;
+                result = Some(SearchResult {
+                    best_move,
+                    score: best_score,
+                    depth,
+                    pv: context.pv().to_vec(),
+                });
+            }
+
+            let elapsed = context.start.elapsed();
+            println!(
+                "info depth {} seldepth {} multipv {} score {} nodes {} nps {} hashfull {} time {} pv {}",
+                depth,
+                context.seldepth,
+                pv_index,
+                uci_score(best_score),
+                context.nodes,
+                (context.nodes as f64 / elapsed.as_secs_f64().max(0.001)) as u64,
+                context.tt.hashfull(),
+                elapsed.as_millis(),
+                context
+                    .pv()
+                    .iter()
+                    .map(Move::uci_notation)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            );
+            context.excluded_moves.push(best_move);
+        }
 
-        found_move = best_move;
+        let Some(best_score) = result.as_ref().map(|result| result.score) else {
+            break;
+        };
 
-        println!("info depth {}", depth);
-        println!("info score cp {}", best_score);
+        if limits.infinite {
+            continue;
+        }
 
         // If mate can be forced, or there is only a single move available, stop searching
-        if is_only_move || best_score > Score::MAX - 1000 || best_score < Score::MIN + 1000 {
-            return found_move;
+        let found_mate = match (moves_to_mate(best_score), limits.mate) {
+            (Some(moves), Some(limit)) => moves > 0 && moves <= limit as i16,
+            (mate, None) => mate.is_some(),
+            (None, Some(_)) => false,
+        };
+        if is_only_move || found_mate {
+            break;
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Score of the position searched to the depth, from the point of view of the current player
+    fn search_score(fen: &str, depth: u8) -> Score {
+        let mut game = ChessGame::new(fen).unwrap();
+        let should_stop = AtomicBool::new(false);
+        let tt = TranspositionTable::new(1);
+        let mut context = SearchContext::new(&should_stop, &tt);
+        get_best_move_score(&mut game, &mut context, depth, 0, -Score::MAX, Score::MAX).unwrap()
+    }
+
+    #[test]
+    fn mate_scores() {
+        let game = ChessGame::new("7k/8/5K2/8/8/8/8/1R6 w - - 0 1").unwrap();
+        let should_stop = AtomicBool::new(false);
+        let tt = TranspositionTable::new(1);
+        let mut context = SearchContext::new(&should_stop, &tt);
+        let mut score = 0;
+        for depth in 1..=4 {
+            score = get_best_move_entry(game.clone(), &mut context, depth)
+                .unwrap()
+                .1;
         }
+        assert_eq!(uci_score(score), "mate 2");
+
+        // After 1. Kg6 black can only delay the mate by a single move
+        assert_eq!(
+            uci_score(search_score("7k/8/6K1/8/8/8/8/1R6 b - - 1 1", 4)),
+            "mate -1"
+        );
+        assert_eq!(uci_score(35), "cp 35");
     }
 
-    unreachable!()
+    #[test]
+    fn mate_scores_in_transposition_table() {
+        let tt = TranspositionTable::new(1);
+        let hash = 0x1234_5678_9abc_def0;
+
+        // Mating 4 plies after a node 3 plies from the root
+        let score = -mated_score(7);
+        tt.store(
+            hash,
+            TableEntry {
+                best_move: None,
+                score: score_to_tt(score, 3),
+                depth: 5,
+                bound: Bound::Exact,
+            },
+        );
+
+        // The same node reached 5 plies from the root still mates 4 plies later
+        let entry = tt.probe(hash).unwrap();
+        assert_eq!(score_from_tt(entry.score, 5), -mated_score(9));
+        assert_eq!(score_from_tt(entry.score, 3), score);
+
+        let score = mated_score(6);
+        assert_eq!(score_from_tt(score_to_tt(score, 2), 4), mated_score(8));
+        assert_eq!(score_from_tt(score_to_tt(120, 2), 4), 120);
+    }
+
+    #[test]
+    fn quiescence_checks() {
+        // Black threatens the quiet back rank mate Ra1#, which white has to prevent
+        let fen = "r5k1/5ppp/8/8/2N4Q/8/5PPP/6K1 w - - 0 1";
+        let game = ChessGame::new(fen).unwrap();
+        let should_stop = AtomicBool::new(false);
+        let tt = TranspositionTable::new(1);
+        let mut context = SearchContext::new(&should_stop, &tt);
+
+        let (best_move, score, _) = get_best_move_entry(game.clone(), &mut context, 1).unwrap();
+        assert_eq!(moves_to_mate(score), None);
+        let ignores_mate = ["c4e5", "c4d6", "c4b6", "c4a5"]
+            .map(|move_str| Move::from_uci_notation(move_str, &game));
+        assert!(!ignores_mate.contains(&best_move));
+
+        // Only the quiescence search sees the mate after a move which ignores it
+        let mut game = game;
+        game.push(Move::from_uci_notation("c4e5", &game).unwrap());
+        let score =
+            -get_best_move_score(&mut game, &mut context, 0, 1, -Score::MAX, Score::MAX).unwrap();
+        assert_eq!(score, mated_score(2));
+        assert_eq!(uci_score(score), "mate -1");
+
+        // White is a queen ahead, but can't stand pat in check
+        game.push(Move::from_uci_notation("a8a1", &game).unwrap());
+        let score = quiescence_search(&mut game, &mut context, 2, -Score::MAX, Score::MAX, false);
+        assert_eq!(score, mated_score(2));
+    }
+
+    #[test]
+    fn repetitions_before_root() {
+        // White is a queen ahead, and has already been in the position once before the search
+        let mut game = ChessGame::new("4k3/8/8/8/8/8/8/3QK1N1 w - - 0 1").unwrap();
+        for move_str in ["g1f3", "e8d8", "f3g1", "d8e8", "g1f3"] {
+            game.push(Move::from_uci_notation(move_str, &game).unwrap());
+        }
+        let should_stop = AtomicBool::new(false);
+        let tt = TranspositionTable::new(1);
+        let mut context = SearchContext::new(&should_stop, &tt);
+
+        let score =
+            -get_best_move_score(&mut game, &mut context, 3, 1, -Score::MAX, Score::MAX).unwrap();
+        assert!(score > 500);
+
+        // The third time is a draw
+        for move_str in ["e8d8", "f3g1", "d8e8", "g1f3"] {
+            game.push(Move::from_uci_notation(move_str, &game).unwrap());
+        }
+        context.tt.clear();
+        let score = get_best_move_score(&mut game, &mut context, 3, 1, -Score::MAX, Score::MAX);
+        assert_eq!(score, Some(0));
+    }
+
+    #[test]
+    fn time_for_move() {
+        let limits = SearchLimits {
+            time: Some(Duration::from_secs(60)),
+            increment: Some(Duration::from_millis(500)),
+            ..Default::default()
+        };
+        let overhead = Duration::from_millis(50);
+        assert_eq!(
+            limits.time_for_move(overhead),
+            Some(Duration::from_millis(1700))
+        );
+
+        // With the clock nearly empty, only the overhead is kept in reserve
+        let limits = SearchLimits {
+            time: Some(Duration::from_millis(80)),
+            increment: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        assert_eq!(
+            limits.time_for_move(overhead),
+            Some(Duration::from_millis(30))
+        );
+        let limits = SearchLimits {
+            time: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        assert_eq!(
+            limits.time_for_move(overhead),
+            Some(Duration::from_millis(1))
+        );
+    }
+
+    #[test]
+    fn full_principal_variation() {
+        let game =
+            ChessGame::new("r1bq1rk1/pp2bppp/2n1pn2/2pp4/3P4/2PBPN2/PP1N1PPP/R1BQ1RK1 w - - 0 8")
+                .unwrap();
+        let should_stop = AtomicBool::new(false);
+        let tt = TranspositionTable::new(1);
+        let mut context = SearchContext::new(&should_stop, &tt);
+
+        // The later lines revisit the positions of the earlier ones through the same table
+        for depth in 1..=6 {
+            context.excluded_moves.clear();
+            for _ in 0..3 {
+                let (best_move, _, _) =
+                    get_best_move_entry(game.clone(), &mut context, depth).unwrap();
+                assert!(context.pv().len() >= depth as usize, "{:?}", context.pv());
+                context.excluded_moves.push(best_move.unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn helper_threads() {
+        let game =
+            ChessGame::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -")
+                .unwrap();
+        let limits = SearchLimits {
+            move_time: Some(Duration::from_millis(300)),
+            ..Default::default()
+        };
+        let options = EngineOptions {
+            threads: 4,
+            ..Default::default()
+        };
+        let tt = TranspositionTable::new(1);
+
+        let start = Instant::now();
+        let result = get_best_move_in_time(
+            &game,
+            &limits,
+            &options,
+            &tt,
+            Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+        // The helpers are stopped along with the main thread
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let mut moves = ArrayVec::new();
+        game.get_moves(&mut moves);
+        assert!(moves.contains(&result.best_move));
+        assert!(result.depth > 1);
+    }
+
+    #[test]
+    fn move_picker_order() {
+        let game = ChessGame::new("4k3/8/4p3/3p4/4P3/8/8/R2QK3 w - - 0 1").unwrap();
+        let parse = |move_str| Move::from_uci_notation(move_str, &game);
+
+        let mut history = Box::new(History([[[0; 64]; 64]; 2]));
+        history.update(Players::White, parse("d1g4").unwrap(), 400);
+        history.update(Players::White, parse("a1b1").unwrap(), 100);
+        history.update(Players::White, parse("d1h5").unwrap(), -100);
+
+        let mut picker = MovePicker::new(
+            &game,
+            parse("a1a7"),
+            [parse("a1a2"), parse("d1d2")],
+            parse("e1f1"),
+            &history,
+        );
+        let mut picked = vec![];
+        while let Some(_move) = picker.next(&game, &history) {
+            picked.push(_move.uci_notation());
+        }
+
+        // Hash move, good capture, killers, countermove, quiets by history, bad capture
+        assert_eq!(
+            picked[..7],
+            ["a1a7", "e4d5", "a1a2", "d1d2", "e1f1", "d1g4", "a1b1"]
+        );
+        assert_eq!(picked[picked.len() - 2], "d1h5");
+        assert_eq!(picked[picked.len() - 1], "d1d5");
+
+        let mut moves = ArrayVec::new();
+        game.get_moves(&mut moves);
+        let mut all_moves: Vec<_> = moves.iter().map(Move::uci_notation).collect();
+        all_moves.sort();
+        picked.sort();
+        assert_eq!(picked, all_moves);
+    }
+
+    #[test]
+    fn history_gravity() {
+        let game = ChessGame::default();
+        let _move = Move::from_uci_notation("e2e4", &game).unwrap();
+        let mut history = Box::new(History([[[0; 64]; 64]; 2]));
+
+        let mut previous = 0;
+        for _ in 0..1000 {
+            history.update(Players::White, _move, 4000);
+            let score = history.get(Players::White, _move);
+            assert!(score >= previous && score <= MAX_HISTORY);
+            previous = score;
+        }
+        assert!(previous > MAX_HISTORY * 9 / 10);
+
+        for _ in 0..1000 {
+            history.update(Players::White, _move, -100_000);
+            assert!(history.get(Players::White, _move) >= -MAX_HISTORY);
+        }
+        assert_eq!(history.get(Players::Black, _move), 0);
+    }
+
+    #[test]
+    fn late_move_reductions() {
+        for remaining_depth in 1..MAX_PLY {
+            for move_count in 1..256 {
+                let reduction = late_move_reduction(remaining_depth, move_count);
+                // A reduced move is still searched at least one move deep
+                assert!(reduction == 0 || remaining_depth - 1 - reduction >= 1);
+                assert!(reduction <= late_move_reduction(remaining_depth, move_count + 1));
+            }
+        }
+        assert_eq!(late_move_reduction(8, 2), 0);
+        assert!(late_move_reduction(20, 40) > late_move_reduction(4, 4));
+        assert!(late_move_pruning_count(3) > late_move_pruning_count(1));
+    }
 }
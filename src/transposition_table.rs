@@ -0,0 +1,164 @@
+// Source: https://www.chessprogramming.org/Transposition_Table
+
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use crate::{move_struct::Move, piece::Score};
+
+pub const DEFAULT_SIZE_MB: usize = 16;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Bound {
+    /// The score is exact
+    Exact = 1,
+    /// The real score is greater or equal (the search failed high)
+    Lower = 2,
+    /// The real score is lower or equal (the search failed low)
+    Upper = 3,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TableEntry {
+    pub best_move: Option<Move>,
+    pub score: Score,
+    pub depth: u8,
+    pub bound: Bound,
+}
+
+/// The key is stored xor-ed with the data, so an entry that was torn by a concurrent
+/// write will simply fail to match its key, instead of returning corrupted data
+///
+/// Source: https://www.chessprogramming.org/Shared_Hash_Table#Lockless
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+/// Fixed size hash table of previously searched positions
+///
+/// All methods take &self, so a single table can be shared between searches
+pub struct TranspositionTable {
+    slots: Box<[Slot]>,
+    /// Incremented for every new search, used to prefer replacing stale entries
+    generation: AtomicU8,
+}
+
+impl TableEntry {
+    /// Layout (from the least significant bit):
+    /// 32 bits move, 16 bits score, 8 bits depth, 2 bits bound, 6 bits generation
+    fn pack(self, generation: u8) -> u64 {
+        let best_move = self.best_move.map_or(0, Move::pack) as u64;
+        best_move
+            | (self.score as u16 as u64) << 32
+            | (self.depth as u64) << 48
+            | (self.bound as u64) << 56
+            | (generation as u64 & 0b111111) << 58
+    }
+
+    fn unpack(data: u64) -> Option<Self> {
+        let bound = match data >> 56 & 0b11 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            3 => Bound::Upper,
+            _ => return None,
+        };
+
+        Some(Self {
+            best_move: Move::unpack(data as u32),
+            score: (data >> 32) as u16 as Score,
+            depth: (data >> 48) as u8,
+            bound,
+        })
+    }
+}
+
+fn data_generation(data: u64) -> u8 {
+    (data >> 58) as u8
+}
+
+fn data_depth(data: u64) -> u8 {
+    (data >> 48) as u8
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        let len = (size_mb * 1024 * 1024 / std::mem::size_of::<Slot>()).max(1);
+        Self {
+            slots: (0..len).map(|_| Slot::default()).collect(),
+            generation: AtomicU8::new(0),
+        }
+    }
+
+    #[inline]
+    fn slot(&self, hash: u64) -> &Slot {
+        // Maps the hash uniformly onto 0..len without requiring a power of two length
+        let index = ((hash as u128 * self.slots.len() as u128) >> 64) as usize;
+        // SAFETY: index is always smaller than the length
+        unsafe { self.slots.get_unchecked(index) }
+    }
+
+    /// Should be called before every search, so that older entries get replaced first
+    pub fn new_search(&self) {
+        let generation = (self.generation.load(Ordering::Relaxed) + 1) & 0b111111;
+        self.generation.store(generation, Ordering::Relaxed);
+    }
+
+    /// Empties the table, so that a new game doesn't use the results of the previous one
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    /// Approximate usage of the table in permille, counting the entries
+    /// stored by the current search among the first thousand
+    pub fn hashfull(&self) -> usize {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let sample = &self.slots[..self.slots.len().min(1000)];
+        let used = sample
+            .iter()
+            .filter(|slot| {
+                let data = slot.data.load(Ordering::Relaxed);
+                data_generation(data) == generation && TableEntry::unpack(data).is_some()
+            })
+            .count();
+        used * 1000 / sample.len()
+    }
+
+    pub fn probe(&self, hash: u64) -> Option<TableEntry> {
+        let slot = self.slot(hash);
+        let data = slot.data.load(Ordering::Relaxed);
+        if slot.key.load(Ordering::Relaxed) ^ data != hash {
+            return None;
+        }
+
+        TableEntry::unpack(data)
+    }
+
+    pub fn store(&self, hash: u64, entry: TableEntry) {
+        let slot = self.slot(hash);
+        let generation = self.generation.load(Ordering::Relaxed);
+        let old_data = slot.data.load(Ordering::Relaxed);
+        let same_position = slot.key.load(Ordering::Relaxed) ^ old_data == hash;
+
+        // Keep deeper results from the current search, unless the new one is exact
+        if data_generation(old_data) == generation
+            && data_depth(old_data) > entry.depth
+            && entry.bound != Bound::Exact
+        {
+            return;
+        }
+
+        let mut entry = entry;
+        // Don't lose the best move of the position if this search didn't find one
+        if entry.best_move.is_none() && same_position {
+            entry.best_move = TableEntry::unpack(old_data).and_then(|old| old.best_move);
+        }
+
+        let data = entry.pack(generation);
+        slot.key.store(hash ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+}
@@ -1,15 +1,186 @@
-use std::{io::stdin, time::Duration};
+use std::{
+    io::stdin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
 
+use anyhow::{bail, Context};
 use arrayvec::ArrayVec;
 
 use crate::{
-    chess_game::{ChessGame, Players},
+    chess_game::{ChessGame, Players, MAX_GAME_LENGTH},
     move_struct::Move,
-    search::get_best_move_in_time,
+    options::{EngineOptions, OPTIONS},
+    search::{get_best_move_in_time, SearchLimits},
+    transposition_table::TranspositionTable,
 };
 
+/// Moves in SAN are accepted as well, which is non standard, but handy when typing by hand
+fn parse_move(move_str: &str, game: &ChessGame) -> Option<Move> {
+    Move::from_uci_notation(move_str, game).or_else(|| Move::from_san(move_str, game).ok())
+}
+
+/// The search running in the background, so that commands can still be read while thinking
+#[derive(Default)]
+struct Search {
+    should_stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+    /// The search only ends when stopped
+    infinite: bool,
+}
+
+impl Search {
+    fn start(
+        &mut self,
+        game: ChessGame,
+        limits: SearchLimits,
+        options: EngineOptions,
+        tt: &Arc<TranspositionTable>,
+    ) {
+        // A new flag for every search, the old one may still be set by its timer
+        self.should_stop = Arc::new(AtomicBool::new(false));
+        let should_stop = self.should_stop.clone();
+        let tt = tt.clone();
+        self.infinite = limits.infinite;
+
+        self.worker = Some(thread::spawn(move || {
+            // If stopped before the first iteration ended, any legal move is better than none
+            let best_move = get_best_move_in_time(&game, &limits, &options, &tt, should_stop)
+                .map(|result| result.best_move)
+                .or_else(|| {
+                    let mut moves = ArrayVec::new();
+                    game.get_moves(&mut moves);
+                    moves.first().copied()
+                });
+
+            match best_move {
+                Some(best_move) => println!("bestmove {}", best_move.uci_notation()),
+                // The null move, there are no legal moves
+                None => println!("bestmove 0000"),
+            }
+        }));
+    }
+
+    /// Halts the search if it is running, and waits for it to print its best move
+    fn stop(&mut self) {
+        self.should_stop.store(true, Ordering::Relaxed);
+        self.wait();
+    }
+
+    fn wait(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            worker.join().unwrap();
+        }
+    }
+}
+
+/// Parses the arguments of the go command
+fn parse_go<'a>(
+    mut terms: impl Iterator<Item = &'a str>,
+    game: &ChessGame,
+    options: &EngineOptions,
+) -> SearchLimits {
+    let mut limits = SearchLimits::default();
+    let (time, increment) = match game.current_player {
+        Players::White => ("wtime", "winc"),
+        Players::Black => ("btime", "binc"),
+    };
+
+    while let Some(term) = terms.next() {
+        let mut millis = || {
+            terms
+                .next()
+                .and_then(|s| s.parse().ok())
+                .map(Duration::from_millis)
+        };
+        match term {
+            _ if term == time => limits.time = millis(),
+            _ if term == increment => limits.increment = millis(),
+            "movetime" => limits.move_time = millis(),
+            "movestogo" => limits.moves_to_go = terms.next().and_then(|s| s.parse().ok()),
+            "depth" => limits.depth = terms.next().and_then(|s| s.parse().ok()),
+            "nodes" => limits.nodes = terms.next().and_then(|s| s.parse().ok()),
+            "mate" => limits.mate = terms.next().and_then(|s| s.parse().ok()),
+            "infinite" => limits.infinite = true,
+            "searchmoves" => {
+                // The moves are the last arguments
+                limits.search_moves = terms
+                    .by_ref()
+                    .map_while(|move_str| parse_move(move_str, game))
+                    .collect();
+            }
+            _ => continue,
+        }
+    }
+
+    let no_limits = limits.depth.is_none()
+        && limits.nodes.is_none()
+        && limits.mate.is_none()
+        && limits.time_for_move(Duration::ZERO).is_none();
+    if !limits.infinite && no_limits {
+        limits.move_time = Some(options.move_time.unwrap_or(Duration::from_secs(2)));
+    }
+
+    limits
+}
+
+/// Parses the arguments of the setoption command, `name <name> [value <value>]`,
+/// returning the name and the value, which is empty for buttons
+fn parse_setoption<'a>(
+    mut terms: impl Iterator<Item = &'a str>,
+) -> anyhow::Result<(String, String)> {
+    if terms.next() != Some("name") {
+        bail!("Missing option name");
+    }
+    // Both the name and the value may contain spaces
+    let name: Vec<_> = terms.by_ref().take_while(|&term| term != "value").collect();
+    let value: Vec<_> = terms.collect();
+    if name.is_empty() {
+        bail!("Missing option name");
+    }
+
+    Ok((name.join(" "), value.join(" ")))
+}
+
+/// Parses the arguments of the position command,
+/// `startpos` or `fen <fen>`, optionally followed by `moves <moves>`
+fn parse_position<'a>(mut terms: impl Iterator<Item = &'a str>) -> anyhow::Result<ChessGame> {
+    let kind = terms.next().context("Missing position")?;
+    let fen: Vec<_> = terms.by_ref().take_while(|&term| term != "moves").collect();
+    let mut game = match kind {
+        "startpos" => ChessGame::default(),
+        "fen" => ChessGame::new(&fen.join(" ")).context("Invalid FEN string")?,
+        _ => bail!("Unknown position {}", kind),
+    };
+
+    for move_str in terms {
+        let Some(_move) = parse_move(move_str, &game) else {
+            bail!("Invalid move {}", move_str);
+        };
+
+        let mut moves = ArrayVec::new();
+        game.get_moves(&mut moves);
+        if !moves.contains(&_move) {
+            bail!("Illegal move {}", move_str);
+        }
+        if game.len() >= MAX_GAME_LENGTH {
+            bail!("Game is longer than {} plies", MAX_GAME_LENGTH);
+        }
+        game.push_history(_move);
+    }
+
+    Ok(game)
+}
+
 pub fn uci_talk() {
     let mut game = ChessGame::default();
+    let mut options = EngineOptions::default();
+    let mut tt = Arc::new(TranspositionTable::new(options.hash_size_mb));
+    let mut search = Search::default();
 
     // Source: https://gist.github.com/DOBRO/2592c6dad754ba67e6dcaec8c90165bf
     'main_loop: for line in stdin().lines() {
@@ -20,6 +191,9 @@ pub fn uci_talk() {
                 "uci" => {
                     println!("id name daniel_chess");
                     println!("id author Malanca Daniel");
+                    for option in &OPTIONS {
+                        println!("{}", option);
+                    }
                     println!("uciok");
                     continue 'main_loop;
                 }
@@ -27,120 +201,149 @@ pub fn uci_talk() {
                     println!("readyok");
                     continue 'main_loop;
                 }
-                "position" => {
-                    if let Some(term) = terms.next() {
-                        match term {
-                            "startpos" => {
-                                game = ChessGame::default();
-                                if let Some(term) = terms.next() {
-                                    if term == "moves" {
-                                        for move_str in terms.by_ref() {
-                                            let Some(_move) =
-                                                Move::from_uci_notation(move_str, &game)
-                                            else {
-                                                continue 'main_loop;
-                                            };
-
-                                            let mut moves = ArrayVec::new();
-                                            game.get_moves(&mut moves, true);
-                                            if moves
-                                                .iter()
-                                                .any(|allowed_move| _move == *allowed_move)
-                                            {
-                                                game.push_history(_move);
-                                                // Hard limit onto the number
-                                                // of possible moves in a game;
-                                                if game.len() >= 400 {
-                                                    continue 'main_loop;
-                                                }
-                                            } else {
-                                                continue 'main_loop;
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            "fen" => {
-                                // TODO: I think it's possible to also get moves
-                                // starting from this position
-                                // i.e. position fen <fen> moves <moves>
-                                let last_terms: Vec<_> = terms.clone().collect();
-                                let fen: String = last_terms.join(" ");
-                                match ChessGame::new(&fen) {
-                                    Ok(fen_game) => {
-                                        game = fen_game;
-                                    }
-                                    Err(err) => {
-                                        eprintln!("{:?}", err.context("invalid FEN string"));
-                                        continue 'main_loop;
-                                    }
-                                }
-                            }
-                            _ => continue 'main_loop,
+                "setoption" => {
+                    // Options are only changed while not searching
+                    search.stop();
+                    let option = parse_setoption(terms.by_ref())
+                        .and_then(|(name, value)| options.set(&name, &value));
+                    match option.map(|option| option.name) {
+                        Ok("Hash") => {
+                            tt = Arc::new(TranspositionTable::new(options.hash_size_mb));
                         }
-                    } else {
-                        continue 'main_loop;
+                        Ok("Clear Hash") => tt.clear(),
+                        Ok(_) => (),
+                        Err(err) => eprintln!("{:?}", err.context("invalid option")),
                     }
+                    continue 'main_loop;
                 }
-                "go" => {
-                    let mut wtime: Option<u64> = None;
-                    let mut btime: Option<u64> = None;
-                    let mut winc: Option<u64> = None;
-                    let mut binc: Option<u64> = None;
-
-                    while let Some(term) = terms.next() {
-                        match term {
-                            "wtime" => wtime = terms.next().and_then(|s| s.parse().ok()),
-                            "btime" => btime = terms.next().and_then(|s| s.parse().ok()),
-                            "winc" => winc = terms.next().and_then(|s| s.parse().ok()),
-                            "binc" => binc = terms.next().and_then(|s| s.parse().ok()),
-                            _ => continue,
-                        }
-                    }
-
-                    const FRACTION_OF_TOTAL_TIME: f64 = 0.02;
-                    let mut time = None;
-
-                    if wtime.is_some() && btime.is_some() && winc.is_some() && binc.is_some() {
-                        let wtime = wtime.unwrap();
-                        let btime = btime.unwrap();
-                        let winc = winc.unwrap();
-                        let binc = binc.unwrap();
-
-                        // We subtract 100ms from the time to make sure we don't run out of time
-                        let white_time =
-                            (wtime as f64 * FRACTION_OF_TOTAL_TIME) as u64 + winc - 100;
-                        let black_time =
-                            (btime as f64 * FRACTION_OF_TOTAL_TIME) as u64 + binc - 100;
-
-                        time = if game.current_player == Players::White {
-                            Some(Duration::from_millis(white_time))
-                        } else {
-                            Some(Duration::from_millis(black_time))
-                        };
+                "ucinewgame" => {
+                    search.stop();
+                    tt.clear();
+                    game = ChessGame::default();
+                    continue 'main_loop;
+                }
+                "position" => {
+                    match parse_position(terms.by_ref()) {
+                        Ok(position) => game = position,
+                        Err(err) => eprintln!("{:?}", err.context("invalid position")),
                     }
+                    continue 'main_loop;
+                }
+                "go" => {
+                    let limits = parse_go(terms.by_ref(), &game, &options);
 
-                    if let Some(env_time) = std::env::var("CHESS_TIME_PER_MOVE")
-                        .ok()
-                        .and_then(|s| s.parse::<u64>().ok())
-                    {
-                        time = Some(Duration::from_millis(env_time));
+                    // The GUI decides whether to claim the draw, so still answer with a move
+                    if game.is_fifty_move_draw() {
+                        println!("info string draw by the fifty-move rule");
                     }
 
-                    println!("info time {:?}", time);
-
-                    if let Some(best_move) =
-                        get_best_move_in_time(&game, time.unwrap_or(Duration::from_secs(2)))
-                    {
-                        println!("bestmove {}", best_move.uci_notation());
-                        game.push_history(best_move);
-                    }
+                    search.stop();
+                    search.start(game.clone(), limits, options.clone(), &tt);
+                    continue 'main_loop;
+                }
+                "stop" => {
+                    search.stop();
+                    continue 'main_loop;
+                }
+                "d" => {
+                    // Non standard command, displays the current position
+                    println!("{}", game);
+                    println!("Fen: {}", game.to_fen());
+                    continue 'main_loop;
                 }
                 "quit" => {
+                    search.stop();
                     return;
                 }
                 _ => continue,
             }
         }
     }
+
+    // The input was closed, the search is left to finish unless it would never do so
+    if search.infinite {
+        search.stop();
+    }
+    search.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position_command() {
+        let parse = |command: &str| parse_position(command.split_ascii_whitespace());
+
+        let game = parse("startpos moves e2e4 c7c5 Nf3").unwrap();
+        assert_eq!(
+            game.to_fen(),
+            "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+
+        let game = parse("fen 4k3/8/8/8/8/8/4P3/4K3 w - - 0 40 moves e2e4 e8d7").unwrap();
+        assert_eq!(game.to_fen(), "8/3k4/8/8/4P3/8/8/4K3 w - - 1 41");
+        assert_eq!(game.move_stack.len(), 2);
+
+        let game = parse("fen 4k3/8/8/8/8/8/4P3/4K3 b - -").unwrap();
+        assert_eq!(game.to_fen(), "4k3/8/8/8/8/8/4P3/4K3 b - - 0 1");
+
+        assert!(parse("startpos moves e2e5").is_err());
+        assert!(parse("fen 4k3/8/8 w - - moves e2e4").is_err());
+        assert!(parse("fen 4k3/8/8/8/8/8/4P3/4K3 w - - 0 1 moves e1e3").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn go_command() {
+        let game = ChessGame::new("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1").unwrap();
+        let options = EngineOptions::default();
+        let parse = |command: &str| parse_go(command.split_ascii_whitespace(), &game, &options);
+        let overhead = options.move_overhead;
+
+        let limits = parse("wtime 1000 btime 60000 winc 0 binc 1000 movestogo 20");
+        assert_eq!(limits.time, Some(Duration::from_secs(60)));
+        assert_eq!(limits.increment, Some(Duration::from_secs(1)));
+        assert_eq!(limits.moves_to_go, Some(20));
+        assert_eq!(
+            limits.time_for_move(overhead),
+            Some(Duration::from_millis(4000))
+        );
+
+        let limits = parse("depth 7 nodes 5000 searchmoves e8d8 e8f8");
+        assert_eq!(limits.depth, Some(7));
+        assert_eq!(limits.nodes, Some(5000));
+        assert_eq!(limits.time_for_move(overhead), None);
+        assert_eq!(limits.search_moves.len(), 2);
+
+        let limits = parse("infinite");
+        assert!(limits.infinite);
+        assert_eq!(limits.time_for_move(overhead), None);
+
+        // Without any limit the search gets a fixed time, which the Move Time option sets
+        assert!(parse("").move_time.is_some());
+        let mut options = options.clone();
+        options.move_time = Some(Duration::from_millis(500));
+        let parse = |command: &str| parse_go(command.split_ascii_whitespace(), &game, &options);
+        assert_eq!(parse("").move_time, Some(Duration::from_millis(500)));
+        assert_eq!(
+            parse("movetime 300").move_time,
+            Some(Duration::from_millis(300))
+        );
+        assert_eq!(parse("depth 5").move_time, None);
+        assert_eq!(parse("btime 60000").move_time, None);
+    }
+
+    #[test]
+    fn setoption_command() {
+        let parse = |command: &str| parse_setoption(command.split_ascii_whitespace());
+
+        let (name, value) = parse("name Move Overhead value 250").unwrap();
+        assert_eq!((name.as_str(), value.as_str()), ("Move Overhead", "250"));
+        let (name, value) = parse("name Clear Hash").unwrap();
+        assert_eq!((name.as_str(), value.as_str()), ("Clear Hash", ""));
+
+        assert!(parse("Hash value 32").is_err());
+        assert!(parse("name value 32").is_err());
+    }
 }
@@ -0,0 +1,110 @@
+// Source: https://www.chessprogramming.org/Zobrist_Hashing
+
+use crate::gamestate::GameState;
+use crate::piece::Piece;
+use crate::position::Position;
+
+/// Small pseudo random number generator, used to fill the key tables at compile time
+/// Source: https://prng.di.unimi.it/splitmix64.c
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    (state, z ^ (z >> 31))
+}
+
+struct Keys {
+    /// Indexed by piece type, then by owner, then by square
+    pieces: [[[u64; 64]; 2]; 6],
+    /// Indexed by the 4 castling bits of the game state
+    castling: [u64; 16],
+    /// Indexed by the en passant column, the last value means no en passant square
+    en_passant: [u64; 9],
+    black_to_move: u64,
+}
+
+const fn generate_keys() -> Keys {
+    let mut keys = Keys {
+        pieces: [[[0; 64]; 2]; 6],
+        castling: [0; 16],
+        en_passant: [0; 9],
+        black_to_move: 0,
+    };
+
+    let mut state = 0x1234_5678_9ABC_DEF0;
+    let mut value;
+
+    let mut piece_type = 0;
+    while piece_type < 6 {
+        let mut owner = 0;
+        while owner < 2 {
+            let mut square = 0;
+            while square < 64 {
+                (state, value) = splitmix64(state);
+                keys.pieces[piece_type][owner][square] = value;
+                square += 1;
+            }
+            owner += 1;
+        }
+        piece_type += 1;
+    }
+
+    // Castling keys are generated per right, and combined for every set of rights
+    let mut rights = [0; 4];
+    let mut right = 0;
+    while right < 4 {
+        (state, value) = splitmix64(state);
+        rights[right] = value;
+        right += 1;
+    }
+    let mut bits = 0;
+    while bits < 16 {
+        let mut right = 0;
+        while right < 4 {
+            if bits & (1 << right) != 0 {
+                keys.castling[bits] ^= rights[right];
+            }
+            right += 1;
+        }
+        bits += 1;
+    }
+
+    let mut col = 0;
+    while col < 8 {
+        (state, value) = splitmix64(state);
+        keys.en_passant[col] = value;
+        col += 1;
+    }
+
+    (_, keys.black_to_move) = splitmix64(state);
+
+    keys
+}
+
+static KEYS: Keys = generate_keys();
+
+#[inline]
+pub fn piece_key(piece: Piece, position: Position) -> u64 {
+    KEYS.pieces[piece.piece_type as usize][piece.owner.as_usize()][position.as_usize()]
+}
+
+/// Key of the castling rights and en passant square of the state
+///
+/// The en passant square is only hashed when a pawn can capture there,
+/// since otherwise the position is the same as without it
+#[inline]
+pub fn state_key(state: GameState) -> u64 {
+    let en_passant = if state.en_passant_capturable() {
+        KEYS.en_passant[state.en_passant() as usize]
+    } else {
+        0
+    };
+    KEYS.castling[state.castling_rights() as usize] ^ en_passant
+}
+
+/// Toggled every time the current player changes
+#[inline]
+pub fn player_key() -> u64 {
+    KEYS.black_to_move
+}